@@ -0,0 +1,50 @@
+use crate::tokenize::{Token, TokenKind};
+
+/// Writes the MML back in the style of the documentation: commands in upper case,
+/// one line for each track and each header, and no other whitespace
+pub fn format(tokens: &[Token]) -> String {
+    let mut formatted = String::new();
+    let mut in_header = false;
+    let mut previous: Option<&TokenKind> = None;
+
+    for (_, token) in tokens {
+        if *token == TokenKind::Character('#')
+            && !formatted.is_empty()
+            && !formatted.ends_with('\n')
+        {
+            formatted.push('\n');
+        }
+        match token {
+            TokenKind::Character(x) => formatted.push(x.to_ascii_uppercase()),
+            TokenKind::Number(x) => {
+                // Numbers in a row would be read as one number without a space
+                if let Some(TokenKind::Number(_)) = previous {
+                    formatted.push(' ');
+                }
+                formatted.push_str(&x.to_string());
+            }
+            TokenKind::BraceString(x) => formatted.push_str(&format!("{{{}}}", x)),
+            TokenKind::QuotedString(_) => formatted.push_str(&token.to_string()),
+        }
+
+        let ends_line = match token {
+            TokenKind::Character('#') => {
+                in_header = true;
+                false
+            }
+            TokenKind::Character(';') => true,
+            TokenKind::Number(_) | TokenKind::QuotedString(_) => in_header,
+            _ => false,
+        };
+        if ends_line {
+            formatted.push('\n');
+            in_header = false;
+        }
+        previous = Some(token);
+    }
+
+    if !formatted.is_empty() && !formatted.ends_with('\n') {
+        formatted.push('\n');
+    }
+    formatted
+}
@@ -0,0 +1,125 @@
+use crate::generate::effects::{Biquad, Effector};
+use crate::generate::partial_min;
+use crate::generate::tones::{Lfsr, Rng};
+use crate::parse::tone::{FilterKind, DEFAULT_FILTER_Q};
+use std::f32::consts::PI;
+
+/// How many differently seeded renders each voice has, so that repeated hits do not sound identical
+pub const DRUM_VARIATIONS: u64 = 4;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum DrumVoice {
+    Kick,
+    Snare,
+    ClosedHiHat,
+    OpenHiHat,
+    Tom,
+    Clap,
+    PeriodicNoise,
+}
+
+impl DrumVoice {
+    /// Maps a pitch to a voice by its note letter, and returns the semitones to tune the voice by
+    pub fn from_pitch(pitch: isize) -> (Self, isize) {
+        let octave = (pitch - 3).div_euclid(12);
+        let (voice, offset) = match (pitch - 3).rem_euclid(12) {
+            x @ 0..=1 => (DrumVoice::Kick, x),
+            x @ 2..=3 => (DrumVoice::Snare, x - 2),
+            4 => (DrumVoice::ClosedHiHat, 0),
+            x @ 5..=6 => (DrumVoice::OpenHiHat, x - 5),
+            x @ 7..=8 => (DrumVoice::Tom, x - 7),
+            x @ 9..=10 => (DrumVoice::Clap, x - 9),
+            _ => (DrumVoice::PeriodicNoise, 0),
+        };
+
+        (voice, octave * 12 + offset)
+    }
+
+    pub fn length(&self) -> f32 {
+        match self {
+            DrumVoice::Kick => 0.35,
+            DrumVoice::Snare => 0.25,
+            DrumVoice::ClosedHiHat => 0.08,
+            DrumVoice::OpenHiHat => 0.4,
+            DrumVoice::Tom => 0.4,
+            DrumVoice::Clap => 0.3,
+            DrumVoice::PeriodicNoise => 0.25,
+        }
+    }
+
+    /// Renders one hit. The same arguments always give the same samples.
+    pub fn render(&self, tune: f32, seed: u64, sample_rate: f32) -> Vec<f32> {
+        let mut rng = Rng::new(seed);
+        let mut lfsr = Lfsr::new(true);
+        let nyquist = sample_rate * 0.45;
+        let filter = |kind, cut_off: f32| {
+            Biquad::new(
+                kind,
+                partial_min(cut_off, nyquist),
+                DEFAULT_FILTER_Q,
+                1.0,
+                sample_rate,
+            )
+        };
+        let low_pass = |cut_off| filter(FilterKind::LowPass, cut_off);
+        let high_pass = |cut_off| filter(FilterKind::HighPass, cut_off);
+        let (mut clap_low_pass, mut clap_high_pass) =
+            (low_pass(2500.0 * tune), high_pass(900.0 * tune));
+        let mut snare_pass = high_pass(1500.0 * tune);
+        let mut hi_hat_pass = high_pass(7000.0 * tune);
+
+        let samples = (self.length() * sample_rate) as usize;
+        let mut phase = 0.0;
+        let mut noise_step = 0.0;
+        let mut periodic = lfsr.step();
+
+        (0..samples)
+            .map(|i| {
+                let x = i as f32 / sample_rate;
+                let decay = |time: f32| (-x / time).exp();
+                match self {
+                    DrumVoice::Kick => {
+                        let frequency = tune * (50.0 + 100.0 * decay(0.03));
+                        phase += 2.0 * PI * frequency / sample_rate;
+                        let click = if x < 0.002 { rng.next_f32() * 0.3 } else { 0.0 };
+                        phase.sin() * decay(0.12) * 0.7 + click
+                    }
+                    DrumVoice::Snare => {
+                        let body = (2.0 * PI * 185.0 * tune * x).sin() * decay(0.04) * 0.5;
+                        let noise = snare_pass.apply(rng.next_f32()) * decay(0.07) * 0.8;
+                        body + noise
+                    }
+                    DrumVoice::ClosedHiHat => hi_hat_pass.apply(rng.next_f32()) * decay(0.015),
+                    DrumVoice::OpenHiHat => hi_hat_pass.apply(rng.next_f32()) * decay(0.12),
+                    DrumVoice::Tom => {
+                        let frequency = tune * (110.0 + 60.0 * decay(0.05));
+                        phase += 2.0 * PI * frequency / sample_rate;
+                        phase.sin() * decay(0.15)
+                    }
+                    DrumVoice::Clap => {
+                        let noise = clap_high_pass.apply(clap_low_pass.apply(rng.next_f32()));
+                        let envelope = (0..3)
+                            .map(|burst| burst as f32 * 0.01)
+                            .filter(|&start| start <= x)
+                            .map(|start| (-(x - start) / 0.005).exp())
+                            .sum::<f32>()
+                            + if x >= 0.03 {
+                                (-(x - 0.03) / 0.08).exp()
+                            } else {
+                                0.0
+                            };
+                        noise * partial_min(envelope, 1.0) * 1.5
+                    }
+                    DrumVoice::PeriodicNoise => {
+                        noise_step += 8000.0 * tune / sample_rate;
+                        while noise_step >= 1.0 {
+                            periodic = lfsr.step();
+                            noise_step -= 1.0;
+                        }
+                        periodic * decay(0.08) * 0.5
+                    }
+                }
+            })
+            .collect()
+    }
+}
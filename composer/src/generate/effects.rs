@@ -1,3 +1,6 @@
+use crate::generate::partial_min;
+use crate::parse::tone::{Effect, FilterKind};
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 
 #[derive(Debug)]
@@ -30,10 +33,84 @@ impl<T: Copy> FixedLengthQueue<T> {
         self.push(modified);
         modified
     }
+
+    /// Changes the length keeping the latest elements
+    pub fn resize(&mut self, length: usize, default: T) {
+        self.elements.rotate_left(self.cursor);
+        let current = self.elements.len();
+        if length < current {
+            self.elements.drain(..current - length);
+        } else {
+            let padding = vec![default; length - current];
+            self.elements.splice(0..0, padding);
+        }
+        self.cursor = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+}
+
+impl FixedLengthQueue<f32> {
+    /// Element pushed `delay` samples ago, where 1 is the latest one, interpolated linearly between samples
+    pub fn delayed(&self, delay: f32) -> f32 {
+        let length = self.elements.len();
+        let delay = delay.max(1.0).min(length as f32);
+        let whole = delay as usize;
+        let fract = delay - whole as f32;
+        let at = |delay: usize| self.elements[(self.cursor + length - delay.min(length)) % length];
+        at(whole) * (1.0 - fract) + at(whole + 1) * fract
+    }
 }
 
 pub trait Effector: std::fmt::Debug + Send + Sync {
     fn apply(&mut self, sample: f32) -> f32;
+
+    /// Takes the parameters of an effect of the same kind, keeping the sound already in the effector
+    fn update(&mut self, effect: &Effect, sample_rate: f32);
+
+    /// Called when a note played through the effector starts
+    fn trigger(&mut self) {}
+}
+
+pub fn effector(effect: &Effect, sample_rate: f32) -> Box<dyn Effector> {
+    match *effect {
+        Effect::Delay { delay, feedback } => Box::new(Delay::new(delay, feedback, sample_rate)),
+        Effect::Filter {
+            kind,
+            cut_off,
+            q,
+            gain,
+        } => Box::new(Biquad::new(kind, cut_off, q, gain, sample_rate)),
+        Effect::FilterEnvelope { from, to, time, q } => {
+            Box::new(FilterEnvelope::new(from, to, time, q, sample_rate))
+        }
+        Effect::Reverb {
+            room_size,
+            damping,
+            mix,
+        } => Box::new(Reverb::new(room_size, damping, mix, sample_rate)),
+        Effect::Chorus { .. } | Effect::Flanger { .. } => {
+            Box::new(ModulatedDelay::new(effect, sample_rate))
+        }
+        Effect::Phaser { rate, depth, mix } => Box::new(Phaser::new(rate, depth, mix, sample_rate)),
+        Effect::Distortion { drive, mix } => Box::new(Distortion::new(drive, mix)),
+        Effect::BitCrusher { bits } => Box::new(BitCrusher::new(bits)),
+        Effect::RateReducer { rate } => Box::new(RateReducer::new(rate, sample_rate)),
+        Effect::Compressor {
+            threshold,
+            ratio,
+            attack,
+            release,
+        } => Box::new(Compressor::new(
+            threshold,
+            ratio,
+            attack,
+            release,
+            sample_rate,
+        )),
+    }
 }
 
 #[derive(Debug)]
@@ -42,13 +119,15 @@ pub struct Delay {
     delayed: FixedLengthQueue<f32>,
 }
 
+fn delay_samples(delay_sec: f32, sample_rate: f32) -> usize {
+    ((delay_sec * sample_rate) as usize).max(1)
+}
+
 impl Delay {
     pub fn new(delay_sec: f32, feedback: f32, sample_rate: f32) -> Self {
-        let delay_samples = (delay_sec * sample_rate) as usize;
-
         Delay {
             feedback,
-            delayed: FixedLengthQueue::new(delay_samples, 0.0),
+            delayed: FixedLengthQueue::new(delay_samples(delay_sec, sample_rate), 0.0),
         }
     }
 }
@@ -58,116 +137,772 @@ impl Effector for Delay {
         let feedback = self.feedback;
         self.delayed.modify(|x| x * feedback + sample)
     }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::Delay { delay, feedback } = *effect {
+            self.feedback = feedback;
+            self.delayed.resize(delay_samples(delay, sample_rate), 0.0);
+        }
+    }
+}
+
+/// Coefficients of a biquad filter normalized by a0
+#[derive(Debug, Clone, Copy, Default)]
+struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl BiquadCoefficients {
+    /// Formulae from the Audio EQ Cookbook by Robert Bristow-Johnson, where `gain` is the ratio of amplitude
+    fn new(kind: FilterKind, cut_off: f32, q: f32, gain: f32, sample_rate: f32) -> Self {
+        let omega = 2.0 * PI * cut_off / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * q.max(0.001));
+        let a = gain.max(0.0).sqrt();
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterKind::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterKind::AllPass => (
+                1.0 - alpha,
+                -2.0 * cos,
+                1.0 + alpha,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a.max(0.001),
+                -2.0 * cos,
+                1.0 - alpha / a.max(0.001),
+            ),
+            FilterKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
 }
 
+/// Filter of any type of the Audio EQ Cookbook. The coefficients are computed only when the parameters change.
 #[derive(Debug)]
-pub struct LowPassFilter {
+pub struct Biquad {
+    kind: FilterKind,
+    coefficients: BiquadCoefficients,
     in1: f32,
     in2: f32,
     out1: f32,
     out2: f32,
-    omega: f32,
-    alpha: f32,
 }
 
-impl LowPassFilter {
-    pub fn new(cut_off: f32, sample_rate: f32) -> Self {
-        let omega = 2.0 * PI * cut_off / sample_rate;
-        let alpha = omega.sin() / (2.0 / (2.0f32.sqrt() / 2.0));
-
-        LowPassFilter {
+impl Biquad {
+    pub fn new(kind: FilterKind, cut_off: f32, q: f32, gain: f32, sample_rate: f32) -> Self {
+        Biquad {
+            kind,
+            coefficients: BiquadCoefficients::new(kind, cut_off, q, gain, sample_rate),
             in1: 0.0,
             in2: 0.0,
             out1: 0.0,
             out2: 0.0,
-            omega,
-            alpha,
         }
     }
+
+    fn set_parameters(&mut self, cut_off: f32, q: f32, gain: f32, sample_rate: f32) {
+        self.coefficients = BiquadCoefficients::new(self.kind, cut_off, q, gain, sample_rate);
+    }
 }
 
-impl Effector for LowPassFilter {
+impl Effector for Biquad {
     fn apply(&mut self, sample: f32) -> f32 {
-        let a0 = 1.0 + self.alpha;
-        let a1 = -2.0 * self.omega.cos();
-        let a2 = 1.0 - self.alpha;
-        let b0 = (1.0 - self.omega.cos()) / 2.0;
-        let b1 = 1.0 - self.omega.cos();
-        let b2 = (1.0 - self.omega.cos()) / 2.0;
-
-        let output = b0 / a0 * sample + b1 / a0 * self.in1 + b2 / a0 * self.in2
-            - a1 / a0 * self.out1
-            - a2 / a0 * self.out2;
-        
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let output = b0 * sample + b1 * self.in1 + b2 * self.in2 - a1 * self.out1 - a2 * self.out2;
+
         self.in2 = self.in1;
         self.in1 = sample;
         self.out2 = self.out1;
         self.out1 = output;
 
-        return output;
+        output
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::Filter {
+            cut_off, q, gain, ..
+        } = *effect
+        {
+            self.set_parameters(cut_off, q, gain, sample_rate);
+        }
     }
 }
 
+/// Low pass filter whose cut off frequency moves exponentially from `from` to `to` in `time` seconds after each note starts.
+/// This is a state variable filter rather than a biquad, as it stays smooth while the frequency moves.
 #[derive(Debug)]
-pub struct HighPassFilter {
-    in1: f32,
-    in2: f32,
-    out1: f32,
-    out2: f32,
-    omega: f32,
-    alpha: f32,
+pub struct FilterEnvelope {
+    from: f32,
+    to: f32,
+    /// Length of the sweep in samples
+    length: f32,
+    q: f32,
+    sample_rate: f32,
+    elapsed: f32,
+    ic1eq: f32,
+    ic2eq: f32,
 }
 
-impl HighPassFilter {
-    pub fn new(cut_off: f32, sample_rate: f32) -> Self {
-        let omega = 2.0 * PI * cut_off / sample_rate;
-        let alpha = omega.sin() / (2.0 / (2.0f32.sqrt() / 2.0));
+impl FilterEnvelope {
+    pub fn new(from: f32, to: f32, time: f32, q: f32, sample_rate: f32) -> Self {
+        FilterEnvelope {
+            from: from.max(1.0),
+            to: to.max(1.0),
+            length: time * sample_rate,
+            q: q.max(0.001),
+            sample_rate,
+            elapsed: f32::INFINITY,
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+        }
+    }
 
-        HighPassFilter {
-            in1: 0.0,
-            in2: 0.0,
-            out1: 0.0,
-            out2: 0.0,
-            omega,
-            alpha,
+    fn cut_off(&self) -> f32 {
+        if self.length <= self.elapsed {
+            return self.to;
         }
+        self.from * (self.to / self.from).powf(self.elapsed / self.length)
     }
 }
 
-impl Effector for HighPassFilter {
+impl Effector for FilterEnvelope {
     fn apply(&mut self, sample: f32) -> f32 {
-        let a0 = 1.0 + self.alpha;
-        let a1 = -2.0 * self.omega.cos();
-        let a2 = 1.0 - self.alpha;
-        let b0 = (1.0 + self.omega.cos()) / 2.0;
-        let b1 = -(1.0 + self.omega.cos());
-        let b2 = (1.0 + self.omega.cos()) / 2.0;
-
-        let output = b0 / a0 * sample + b1 / a0 * self.in1 + b2 / a0 * self.in2
-            - a1 / a0 * self.out1
-            - a2 / a0 * self.out2;
-        
-        self.in2 = self.in1;
-        self.in1 = sample;
-        self.out2 = self.out1;
-        self.out1 = output;
+        let cut_off = partial_min(self.cut_off(), self.sample_rate * 0.49);
+        self.elapsed += 1.0;
+
+        let g = (PI * cut_off / self.sample_rate).tan();
+        let k = 1.0 / self.q;
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = sample - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        v2
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::FilterEnvelope { from, to, time, q } = *effect {
+            self.from = from.max(1.0);
+            self.to = to.max(1.0);
+            self.length = time * sample_rate;
+            self.q = q.max(0.001);
+        }
+    }
 
-        return output;
+    fn trigger(&mut self) {
+        self.elapsed = 0.0;
     }
 }
 
+/// Comb filter with a low pass filter in the feedback loop
+#[derive(Debug)]
+struct DampedComb {
+    buffer: FixedLengthQueue<f32>,
+    filtered: f32,
+}
+
+impl DampedComb {
+    fn apply(&mut self, sample: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer.peek();
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer.push(sample + self.filtered * feedback);
+        output
+    }
+}
+
+#[derive(Debug)]
+struct AllPass {
+    buffer: FixedLengthQueue<f32>,
+}
+
+impl AllPass {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let buffered = self.buffer.peek();
+        self.buffer.push(sample + buffered * 0.5);
+        buffered - sample
+    }
+}
+
+/// Lengths in samples at 44.1kHz, from Freeverb
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALL_PASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+
+/// Freeverb. Only the left channel is computed as the generator is mono.
+#[derive(Debug)]
+pub struct Reverb {
+    combs: Vec<DampedComb>,
+    all_passes: Vec<AllPass>,
+    feedback: f32,
+    damping: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    pub fn new(room_size: f32, damping: f32, mix: f32, sample_rate: f32) -> Self {
+        let samples = |length: usize| ((length as f32 * sample_rate / 44100.0) as usize).max(1);
+        let combs = COMB_TUNINGS
+            .iter()
+            .map(|&length| DampedComb {
+                buffer: FixedLengthQueue::new(samples(length), 0.0),
+                filtered: 0.0,
+            })
+            .collect();
+        let all_passes = ALL_PASS_TUNINGS
+            .iter()
+            .map(|&length| AllPass {
+                buffer: FixedLengthQueue::new(samples(length), 0.0),
+            })
+            .collect();
+
+        let mut reverb = Reverb {
+            combs,
+            all_passes,
+            feedback: 0.0,
+            damping: 0.0,
+            mix: 0.0,
+        };
+        reverb.set_params(room_size, damping, mix);
+        reverb
+    }
+
+    fn set_params(&mut self, room_size: f32, damping: f32, mix: f32) {
+        self.feedback = room_size.clamp(0.0, 1.0) * 0.28 + 0.7;
+        self.damping = damping.clamp(0.0, 1.0) * 0.4;
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+impl Effector for Reverb {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let (feedback, damping) = (self.feedback, self.damping);
+        let input = sample * 0.015;
+        let combed: f32 = self
+            .combs
+            .iter_mut()
+            .map(|comb| comb.apply(input, feedback, damping))
+            .sum();
+        let wet = self
+            .all_passes
+            .iter_mut()
+            .fold(combed, |sample, all_pass| all_pass.apply(sample));
+
+        sample * (1.0 - self.mix) + wet * 3.0 * self.mix
+    }
+
+    fn update(&mut self, effect: &Effect, _: f32) {
+        if let Effect::Reverb {
+            room_size,
+            damping,
+            mix,
+        } = *effect
+        {
+            self.set_params(room_size, damping, mix);
+        }
+    }
+}
+
+/// Low frequency oscillator going between 0 and 1
+#[derive(Debug)]
+struct Lfo {
+    phase: f32,
+    increment: f32,
+}
+
+impl Lfo {
+    fn new(rate: f32, sample_rate: f32) -> Self {
+        Lfo {
+            phase: 0.0,
+            increment: rate / sample_rate,
+        }
+    }
+
+    fn advance(&mut self) -> f32 {
+        let value = 0.5 - (2.0 * PI * self.phase).cos() / 2.0;
+        self.phase = (self.phase + self.increment).fract();
+        value
+    }
+}
+
+const CHORUS_DELAY: f32 = 0.02;
+const FLANGER_DELAY: f32 = 0.001;
+/// Longest depth in seconds, which bounds the size of the buffer
+const MAX_DEPTH: f32 = 0.1;
+
+/// Chorus and flanger, whose delay time sways between the base delay and the base delay plus the depth
+#[derive(Debug)]
+pub struct ModulatedDelay {
+    lfo: Lfo,
+    delayed: FixedLengthQueue<f32>,
+    /// Base delay and depth in samples
+    base: f32,
+    depth: f32,
+    feedback: f32,
+    mix: f32,
+}
+
+impl ModulatedDelay {
+    pub fn new(effect: &Effect, sample_rate: f32) -> Self {
+        let mut modulated = ModulatedDelay {
+            lfo: Lfo::new(0.0, sample_rate),
+            delayed: FixedLengthQueue::new(1, 0.0),
+            base: 0.0,
+            depth: 0.0,
+            feedback: 0.0,
+            mix: 0.0,
+        };
+        modulated.update(effect, sample_rate);
+        modulated
+    }
+}
+
+impl Effector for ModulatedDelay {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let delay = self.base + self.depth * self.lfo.advance();
+        let wet = self.delayed.delayed(delay);
+        self.delayed.push(sample + wet * self.feedback);
+        sample * (1.0 - self.mix) + wet * self.mix
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        let (rate, base, depth, feedback, mix) = match *effect {
+            Effect::Chorus { rate, depth, mix } => (rate, CHORUS_DELAY, depth, 0.0, mix),
+            Effect::Flanger {
+                rate,
+                depth,
+                feedback,
+                mix,
+            } => (rate, FLANGER_DELAY, depth, feedback, mix),
+            _ => return,
+        };
+
+        self.lfo.increment = rate / sample_rate;
+        self.base = base * sample_rate;
+        self.depth = depth.clamp(0.0, MAX_DEPTH) * sample_rate;
+        self.feedback = feedback.clamp(0.0, 0.99);
+        self.mix = mix.clamp(0.0, 1.0);
+
+        let length = (self.base + self.depth) as usize + 2;
+        if length != self.delayed.len() {
+            self.delayed.resize(length, 0.0);
+        }
+    }
+}
+
+const PHASER_STAGES: usize = 4;
+const PHASER_MIN_FREQUENCY: f32 = 200.0;
+/// Octaves swept with the depth of 1
+const PHASER_OCTAVES: f32 = 5.0;
+
+/// First order all pass filters whose corner frequency is swept by an LFO
+#[derive(Debug)]
+pub struct Phaser {
+    lfo: Lfo,
+    stages: [(f32, f32); PHASER_STAGES],
+    depth: f32,
+    mix: f32,
+    sample_rate: f32,
+}
+
+impl Phaser {
+    pub fn new(rate: f32, depth: f32, mix: f32, sample_rate: f32) -> Self {
+        Phaser {
+            lfo: Lfo::new(rate, sample_rate),
+            stages: [(0.0, 0.0); PHASER_STAGES],
+            depth: depth.clamp(0.0, 1.0),
+            mix: mix.clamp(0.0, 1.0),
+            sample_rate,
+        }
+    }
+}
+
+impl Effector for Phaser {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let octaves = self.depth * PHASER_OCTAVES * self.lfo.advance();
+        let frequency = (PHASER_MIN_FREQUENCY * octaves.exp2()).min(self.sample_rate * 0.45);
+        let tan = (PI * frequency / self.sample_rate).tan();
+        let coefficient = (tan - 1.0) / (tan + 1.0);
+
+        let wet = self.stages.iter_mut().fold(sample, |input, (in1, out1)| {
+            let output = coefficient * input + *in1 - coefficient * *out1;
+            *in1 = input;
+            *out1 = output;
+            output
+        });
+        sample * (1.0 - self.mix) + wet * self.mix
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::Phaser { rate, depth, mix } = *effect {
+            self.lfo.increment = rate / sample_rate;
+            self.depth = depth.clamp(0.0, 1.0);
+            self.mix = mix.clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// Soft clipping overdrive
+#[derive(Debug)]
+pub struct Distortion {
+    drive: f32,
+    mix: f32,
+}
+
+impl Distortion {
+    pub fn new(drive: f32, mix: f32) -> Self {
+        Distortion {
+            drive: drive.max(0.0),
+            mix: mix.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Effector for Distortion {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let wet = (sample * self.drive).tanh();
+        sample * (1.0 - self.mix) + wet * self.mix
+    }
+
+    fn update(&mut self, effect: &Effect, _: f32) {
+        if let Effect::Distortion { drive, mix } = *effect {
+            *self = Distortion::new(drive, mix);
+        }
+    }
+}
+
+/// Quantizes samples to the bit depth, where 0 bits is treated as 1
+#[derive(Debug)]
+pub struct BitCrusher {
+    steps: f32,
+}
+
+impl BitCrusher {
+    pub fn new(bits: usize) -> Self {
+        BitCrusher {
+            steps: 2.0f32.powi(bits.clamp(1, 24) as i32 - 1),
+        }
+    }
+}
+
+impl Effector for BitCrusher {
+    fn apply(&mut self, sample: f32) -> f32 {
+        (sample * self.steps).round() / self.steps
+    }
+
+    fn update(&mut self, effect: &Effect, _: f32) {
+        if let Effect::BitCrusher { bits } = *effect {
+            *self = BitCrusher::new(bits);
+        }
+    }
+}
+
+/// Sample and hold at a lower sample rate
+#[derive(Debug)]
+pub struct RateReducer {
+    increment: f32,
+    phase: f32,
+    held: f32,
+}
+
+impl RateReducer {
+    pub fn new(rate: f32, sample_rate: f32) -> Self {
+        RateReducer {
+            increment: (rate / sample_rate).min(1.0),
+            phase: 1.0,
+            held: 0.0,
+        }
+    }
+}
+
+impl Effector for RateReducer {
+    fn apply(&mut self, sample: f32) -> f32 {
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.held = sample;
+        }
+        self.phase += self.increment;
+        self.held
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::RateReducer { rate } = *effect {
+            self.increment = (rate / sample_rate).min(1.0);
+        }
+    }
+}
+
+/// Coefficient of a one pole smoother which moves about 63% of the way in `time` seconds
+fn smoothing_coefficient(time: f32, sample_rate: f32) -> f32 {
+    if time <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (time * sample_rate)).exp()
+    }
+}
+
+/// Feed forward compressor following the peak of the input
+#[derive(Debug)]
+pub struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(threshold: f32, ratio: f32, attack: f32, release: f32, sample_rate: f32) -> Self {
+        Compressor {
+            threshold: threshold.max(0.001),
+            ratio: ratio.max(1.0),
+            attack: smoothing_coefficient(attack, sample_rate),
+            release: smoothing_coefficient(release, sample_rate),
+            envelope: 0.0,
+        }
+    }
+}
+
+impl Effector for Compressor {
+    fn apply(&mut self, sample: f32) -> f32 {
+        let level = sample.abs();
+        let coefficient = if self.envelope < level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + (self.envelope - level) * coefficient;
+
+        if self.envelope <= self.threshold {
+            return sample;
+        }
+        let compressed = self.threshold * (self.envelope / self.threshold).powf(1.0 / self.ratio);
+        sample * compressed / self.envelope
+    }
+
+    fn update(&mut self, effect: &Effect, sample_rate: f32) {
+        if let Effect::Compressor {
+            threshold,
+            ratio,
+            attack,
+            release,
+        } = *effect
+        {
+            let envelope = self.envelope;
+            *self = Compressor::new(threshold, ratio, attack, release, sample_rate);
+            self.envelope = envelope;
+        }
+    }
+}
+
+const LIMITER_LOOK_AHEAD: f32 = 0.005;
+const LIMITER_RELEASE: f32 = 0.05;
+
+/// Keeps samples within `ceiling`. The gain goes down before a peak arrives, so the output is the input delayed by
+/// `latency` samples.
+#[derive(Debug)]
+pub struct Limiter {
+    ceiling: f32,
+    delayed: FixedLengthQueue<f32>,
+    /// Gains required by the samples in the delay, as (index, gain) increasing in both
+    required: VecDeque<(usize, f32)>,
+    index: usize,
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(ceiling: f32, sample_rate: f32) -> Self {
+        Limiter {
+            ceiling,
+            delayed: FixedLengthQueue::new(delay_samples(LIMITER_LOOK_AHEAD, sample_rate), 0.0),
+            required: VecDeque::new(),
+            index: 0,
+            gain: 1.0,
+            release: smoothing_coefficient(LIMITER_RELEASE, sample_rate),
+        }
+    }
+
+    pub fn latency(&self) -> usize {
+        self.delayed.len()
+    }
+
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        let required = if sample.abs() > self.ceiling {
+            self.ceiling / sample.abs()
+        } else {
+            1.0
+        };
+        while matches!(self.required.back(), Some(&(_, gain)) if required <= gain) {
+            self.required.pop_back();
+        }
+        self.required.push_back((self.index, required));
+        let oldest = self.index.saturating_sub(self.latency());
+        while matches!(self.required.front(), Some(&(index, _)) if index < oldest) {
+            self.required.pop_front();
+        }
+        self.index += 1;
+
+        // The lowest gain required by a sample in the delay, including the one going out
+        let target = self.required.front().map_or(1.0, |&(_, gain)| gain);
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.release
+        };
+        self.delayed.push(sample) * self.gain
+    }
+}
+
+/// Output quieter than this counts as silence
+const SILENCE: f32 = 1e-6;
+/// Seconds of silence after which a chain without input is left alone, on top of its delays
+const IDLE_TIME: f32 = 0.1;
+
+/// Effects applied in order, where each kind of effect appears at most once
+#[derive(Debug, Default)]
+pub struct EffectChain {
+    effects: Vec<(Effect, Box<dyn Effector>)>,
+    /// Samples for which the chain has had no input and given silence
+    silent: usize,
+    /// Samples of silence after which nothing is left in the delays of the chain
+    idle_after: usize,
+}
+
+impl EffectChain {
+    /// Adds an effect, or changes the parameters if the same kind of effect is already in the chain
+    pub fn set(&mut self, effect: &Effect, sample_rate: f32) {
+        let current = self
+            .effects
+            .iter_mut()
+            .find(|(current, _)| current.same_kind(effect));
+
+        match current {
+            Some((current, effector)) => {
+                effector.update(effect, sample_rate);
+                *current = *effect;
+            }
+            None => self.effects.push((*effect, effector(effect, sample_rate))),
+        }
+
+        let delays: f32 = self
+            .effects
+            .iter()
+            .map(|(effect, _)| match effect {
+                Effect::Delay { delay, .. } => *delay,
+                _ => 0.0,
+            })
+            .sum();
+        self.idle_after = ((IDLE_TIME + delays) * sample_rate) as usize;
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn trigger(&mut self) {
+        for (_, effector) in self.effects.iter_mut() {
+            effector.trigger();
+        }
+    }
+
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        let output = self
+            .effects
+            .iter_mut()
+            .fold(sample, |sample, (_, effector)| effector.apply(sample));
+        if sample == 0.0 && output.abs() < SILENCE {
+            self.silent += 1;
+        } else {
+            self.silent = 0;
+        }
+        output
+    }
+
+    /// Whether the chain gives only silence until it gets input again
+    pub fn is_idle(&self) -> bool {
+        self.silent > self.idle_after
+    }
+}
+
+/// Change of an effect chain. Chain 0 is the one of the whole track.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectEvent {
+    Set(usize, Effect),
+    Clear(usize),
+    /// A note played through the chain starts
+    Trigger(usize),
+    /// Level of the track sent to a bus
+    Send(usize, f32),
+}
+
 #[derive(Debug)]
 pub struct EffectsQueue {
-    effects: Vec<(f32, Box<dyn Effector>)>,
+    effects: Vec<(f32, EffectEvent)>,
 }
 
 impl EffectsQueue {
-    pub fn new(mut effects: Vec<(f32, Box<dyn Effector>)>) -> Self {
-        effects.sort_unstable_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap());
+    /// Events at the same position keep their order
+    pub fn new(mut effects: Vec<(f32, EffectEvent)>) -> Self {
+        effects.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        effects.reverse();
         EffectsQueue { effects }
     }
 
-    pub fn next_before(&mut self, before: f32) -> Option<Box<dyn Effector>> {
+    pub fn next_before(&mut self, before: f32) -> Option<EffectEvent> {
         if self.effects.last()?.0 > before {
             return None;
         }
@@ -175,7 +910,7 @@ impl EffectsQueue {
         Some(self.effects.remove(self.effects.len() - 1).1)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item=&(f32, Box<dyn Effector>)> {
+    pub fn iter(&self) -> impl Iterator<Item = &(f32, EffectEvent)> {
         self.effects.iter()
     }
 
@@ -0,0 +1,334 @@
+use crate::generate::tags::vorbis_comments;
+use crate::generate::wav::Quantizer;
+use crate::parse::Metadata;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+const MAX_RICE_PARAMETER: u32 = 14;
+const STREAMINFO_LENGTH: usize = 34;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlacOptions {
+    /// 8, 12, 16, 20 or 24
+    pub bits: u16,
+    pub dither: bool,
+}
+
+impl Default for FlacOptions {
+    fn default() -> Self {
+        FlacOptions {
+            bits: 16,
+            dither: false,
+        }
+    }
+}
+
+/// Writes values of any number of bits from the most significant one
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits used in the last byte, where 0 means that it is full
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let bit = (value >> i) as u8 & 1;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1 << bits) - 1), bits);
+    }
+
+    /// `value` zeros followed by a one
+    fn write_unary(&mut self, value: u64) {
+        for _ in 0..value {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    fn align(&mut self) {
+        self.used = 0;
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Frame numbers are coded like UTF-8, but with up to 36 bits
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+
+    // The first byte has as many leading ones as the bytes, and 5n + 1 bits are left for the value
+    let length = (2..=7).find(|&n| value < 1 << (5 * n + 1)).unwrap_or(7);
+    let lead = (0xff00 >> length) & 0xff;
+    writer.write(lead | value >> (6 * (length - 1)), 8);
+    for i in (0..length - 1).rev() {
+        writer.write(0x80 | (value >> (6 * i)) & 0x3f, 8);
+    }
+}
+
+fn zigzag(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// Residuals of the fixed polynomial predictor of the order, starting from the sample at `order`
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    let mut residuals = samples.to_vec();
+    for _ in 0..order {
+        for i in (1..residuals.len()).rev() {
+            residuals[i] -= residuals[i - 1];
+        }
+    }
+    residuals.split_off(order)
+}
+
+/// Bits and the parameter of the best Rice code of a partition
+fn rice_parameter(residuals: &[i64]) -> (u64, u32) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let quotients: u64 = residuals.iter().map(|&x| zigzag(x) >> k).sum();
+            (quotients + residuals.len() as u64 * (k as u64 + 1), k)
+        })
+        .min()
+        .unwrap()
+}
+
+/// Splits the residuals into 2^order partitions, the first of which lacks the warm-up samples
+fn partitions(residuals: &[i64], block_size: usize, predictor: usize, order: u32) -> Vec<&[i64]> {
+    let size = block_size >> order;
+    let mut partitions = vec![&residuals[..size - predictor]];
+    partitions.extend(residuals[size - predictor..].chunks(size));
+    partitions
+}
+
+/// Best partition order and the Rice parameters of the partitions
+fn rice_partitions(residuals: &[i64], block_size: usize, predictor: usize) -> (u64, u32, Vec<u32>) {
+    (0..=MAX_PARTITION_ORDER)
+        .take_while(|&order| {
+            block_size.trailing_zeros() >= order && block_size >> order > predictor
+        })
+        .map(|order| {
+            let (bits, parameters) = partitions(residuals, block_size, predictor, order)
+                .into_iter()
+                .map(rice_parameter)
+                .fold((0, Vec::new()), |(sum, mut parameters), (bits, k)| {
+                    parameters.push(k);
+                    (sum + bits + 4, parameters)
+                });
+            (bits, order, parameters)
+        })
+        .min_by_key(|(bits, _, _)| *bits)
+        .unwrap()
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|&x| x == samples[0]) {
+        writer.write(0, 8); // Constant
+        writer.write_signed(samples[0], bits);
+        return;
+    }
+
+    let order = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .min_by_key(|&order| {
+            let residuals = fixed_residuals(samples, order);
+            residuals.iter().map(|x| x.unsigned_abs()).sum::<u64>()
+        })
+        .unwrap();
+    let residuals = fixed_residuals(samples, order);
+    let (rice_bits, partition_order, parameters) =
+        rice_partitions(&residuals, samples.len(), order);
+
+    let fixed_bits = rice_bits + 6 + (order * bits as usize) as u64;
+    if fixed_bits >= (samples.len() * bits as usize) as u64 {
+        writer.write(0b0000_0010, 8); // Verbatim
+        for &sample in samples {
+            writer.write_signed(sample, bits);
+        }
+        return;
+    }
+
+    writer.write(0b0001_0000 | (order as u64) << 1, 8); // Fixed
+    for &sample in &samples[..order] {
+        writer.write_signed(sample, bits);
+    }
+    writer.write(0, 2); // Rice coding with 4-bit parameters
+    writer.write(partition_order as u64, 4);
+    let partitions = partitions(&residuals, samples.len(), order, partition_order);
+    for (partition, &k) in partitions.into_iter().zip(parameters.iter()) {
+        writer.write(k as u64, 4);
+        for &residual in partition {
+            let value = zigzag(residual);
+            writer.write_unary(value >> k);
+            writer.write(value & ((1 << k) - 1), k);
+        }
+    }
+}
+
+/// Other sizes can only be given in the STREAMINFO, which some decoders do not support
+fn sample_size_code(bits: u16) -> Option<u64> {
+    match bits {
+        8 => Some(0b001),
+        12 => Some(0b010),
+        16 => Some(0b100),
+        20 => Some(0b101),
+        24 => Some(0b110),
+        _ => None,
+    }
+}
+
+/// Writes a mono FLAC file block by block, filling in the STREAMINFO when it is finished
+#[derive(Debug)]
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    bits: u16,
+    size_code: u64,
+    quantizer: Quantizer,
+    block: Vec<i64>,
+    frames: u64,
+    samples: u64,
+    frame_sizes: Option<(usize, usize)>,
+    streaminfo_at: u64,
+    sample_rate: u32,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        options: FlacOptions,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let size_code = match sample_size_code(options.bits) {
+            Some(code) => code,
+            None => {
+                let message = "FLAC samples must have 8, 12, 16, 20 or 24 bits";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        };
+
+        writer.write_all(b"fLaC")?;
+        let streaminfo_at = writer.stream_position()? + 4;
+        writer.write_all(&[0, 0, 0, STREAMINFO_LENGTH as u8])?;
+        writer.write_all(&[0; STREAMINFO_LENGTH])?; // Overwritten when finished
+
+        // Vorbis comments, which are the last metadata block
+        let comments = vorbis_comments(metadata);
+        let length = (comments.len() as u32).to_be_bytes();
+        writer.write_all(&[0x80 | 4, length[1], length[2], length[3]])?;
+        writer.write_all(&comments)?;
+
+        Ok(FlacWriter {
+            writer,
+            bits: options.bits,
+            size_code,
+            quantizer: Quantizer::new(options.bits, options.dither),
+            block: Vec::with_capacity(BLOCK_SIZE),
+            frames: 0,
+            samples: 0,
+            frame_sizes: None,
+            streaminfo_at,
+            sample_rate,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.block.push(self.quantizer.quantize(sample) as i64);
+        if self.block.len() == BLOCK_SIZE {
+            self.write_frame()?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let mut frame = BitWriter::default();
+        frame.write(0b1111_1111_1111_1000, 16); // Sync code with fixed block sizes
+        frame.write(0b0111, 4); // Block size in 16 bits at the end of the header
+        frame.write(0b0000, 4); // Sample rate in the STREAMINFO
+        frame.write(0b0000, 4); // Mono
+        frame.write(self.size_code, 3);
+        frame.write(0, 1);
+        write_utf8(&mut frame, self.frames);
+        frame.write(self.block.len() as u64 - 1, 16);
+        let crc = crc8(&frame.bytes);
+        frame.write(crc as u64, 8);
+
+        write_subframe(&mut frame, &self.block, self.bits as u32);
+        frame.align();
+        let crc = crc16(&frame.bytes);
+        frame.write(crc as u64, 16);
+        self.writer.write_all(&frame.bytes)?;
+
+        let size = frame.bytes.len();
+        self.frame_sizes = Some(match self.frame_sizes {
+            Some((min, max)) => (min.min(size), max.max(size)),
+            None => (size, size),
+        });
+        self.frames += 1;
+        self.samples += self.block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the STREAMINFO, and returns the writer positioned at the end of the file
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() {
+            self.write_frame()?;
+        }
+        let end = self.writer.stream_position()?;
+
+        let (min_frame, max_frame) = self.frame_sizes.unwrap_or((0, 0));
+        let mut info = BitWriter::default();
+        // The last block can be smaller, which does not count as the minimum
+        info.write(BLOCK_SIZE as u64, 16);
+        info.write(BLOCK_SIZE as u64, 16);
+        info.write(min_frame as u64, 24);
+        info.write(max_frame as u64, 24);
+        info.write(self.sample_rate as u64, 20);
+        info.write(0, 3); // Mono
+        info.write(self.bits as u64 - 1, 5);
+        info.write(self.samples, 36);
+        info.write(0, 64); // The MD5 signature is left unknown
+        info.write(0, 64);
+
+        self.writer.seek(SeekFrom::Start(self.streaminfo_at))?;
+        self.writer.write_all(&info.bytes)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
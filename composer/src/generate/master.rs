@@ -0,0 +1,194 @@
+use crate::generate::effects::Limiter;
+
+/// Gain applied to the sum of the tracks by default, which is what the generator has always used
+pub const DEFAULT_MASTER_GAIN: f32 = 0.25;
+
+/// How the whole song is scaled after it is rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Makes the highest peak this amplitude
+    Peak(f32),
+    /// Makes the integrated loudness this many LUFS
+    Loudness(f32),
+}
+
+/// Processing of the sum of all tracks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MasterOptions {
+    pub gain: f32,
+    /// Keeps the output within [-1, 1] with a look-ahead limiter instead of clipping it
+    pub limiter: bool,
+    /// Only applied when the whole song is rendered at once, as it needs two passes
+    pub normalization: Option<Normalization>,
+}
+
+impl Default for MasterOptions {
+    fn default() -> Self {
+        MasterOptions {
+            gain: DEFAULT_MASTER_GAIN,
+            limiter: true,
+            normalization: None,
+        }
+    }
+}
+
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, x| peak.max(x.abs()))
+}
+
+const BLOCK_LENGTH: f32 = 0.4;
+const BLOCK_STEP: f32 = 0.1;
+const ABSOLUTE_GATE: f32 = -70.0;
+const RELATIVE_GATE: f32 = -10.0;
+
+/// One stage of the K-weighting filter, designed as in libebur128 so that it works at any sample rate
+#[derive(Debug)]
+struct KWeighting {
+    b: [f64; 3],
+    a: [f64; 2],
+    input: [f64; 2],
+    output: [f64; 2],
+}
+
+impl KWeighting {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        KWeighting {
+            b,
+            a,
+            input: [0.0; 2],
+            output: [0.0; 2],
+        }
+    }
+
+    /// Models the acoustic effect of the head
+    fn shelf(sample_rate: f64) -> Self {
+        let (frequency, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let vh = 10.0f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let b = [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ];
+        let a = [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0];
+        KWeighting::new(b, a)
+    }
+
+    /// The revised low-frequency B-curve
+    fn high_pass(sample_rate: f64) -> Self {
+        let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let a = [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0];
+        KWeighting::new([1.0, -2.0, 1.0], a)
+    }
+
+    fn apply(&mut self, sample: f64) -> f64 {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        let output = b0 * sample + b1 * self.input[0] + b2 * self.input[1]
+            - a1 * self.output[0]
+            - a2 * self.output[1];
+        self.input = [sample, self.input[0]];
+        self.output = [output, self.output[0]];
+        output
+    }
+}
+
+fn block_loudness(mean_square: f32) -> f32 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of a mono signal in LUFS as defined in ITU-R BS.1770-4, or negative infinity for silence
+pub fn loudness(samples: &[f32], sample_rate: f32) -> f32 {
+    let mut shelf = KWeighting::shelf(sample_rate as f64);
+    let mut high_pass = KWeighting::high_pass(sample_rate as f64);
+    let squares: Vec<_> = samples
+        .iter()
+        .map(|&x| high_pass.apply(shelf.apply(x as f64)).powi(2))
+        .collect();
+
+    let block_length = (BLOCK_LENGTH * sample_rate) as usize;
+    let block_step = ((BLOCK_STEP * sample_rate) as usize).max(1);
+    if block_length == 0 || squares.len() < block_length {
+        return f32::NEG_INFINITY;
+    }
+    let blocks: Vec<_> = (0..=(squares.len() - block_length))
+        .step_by(block_step)
+        .map(|start| {
+            let block = &squares[start..start + block_length];
+            (block.iter().sum::<f64>() / block_length as f64) as f32
+        })
+        .filter(|&mean_square| block_loudness(mean_square) > ABSOLUTE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+
+    let mean = |blocks: &mut dyn Iterator<Item = f32>| {
+        let (sum, count) = blocks.fold((0.0, 0), |(sum, count), x| (sum + x, count + 1));
+        sum / count as f32
+    };
+    let gate = block_loudness(mean(&mut blocks.iter().copied())) + RELATIVE_GATE;
+    let gated = mean(
+        &mut blocks
+            .iter()
+            .copied()
+            .filter(|&mean_square| block_loudness(mean_square) > gate),
+    );
+    block_loudness(gated)
+}
+
+/// Scales the samples to the target. Silence is left as it is.
+pub fn normalize(samples: &mut [f32], normalization: Normalization, sample_rate: f32) {
+    let gain = match normalization {
+        Normalization::Peak(target) => target / peak(samples),
+        Normalization::Loudness(target) => {
+            10.0f32.powf((target - loudness(samples, sample_rate)) / 20.0)
+        }
+    };
+    if !gain.is_finite() || gain <= 0.0 {
+        return;
+    }
+
+    for sample in samples.iter_mut() {
+        *sample *= gain;
+    }
+}
+
+/// The last stage of the output, which either limits or clips the samples
+#[derive(Debug)]
+pub enum Ceiling {
+    Limiter(Limiter),
+    Clip,
+}
+
+impl Ceiling {
+    pub fn new(options: &MasterOptions, sample_rate: f32) -> Self {
+        if options.limiter {
+            Ceiling::Limiter(Limiter::new(1.0, sample_rate))
+        } else {
+            Ceiling::Clip
+        }
+    }
+
+    /// How many samples the output is behind the input
+    pub fn latency(&self) -> usize {
+        match self {
+            Ceiling::Limiter(limiter) => limiter.latency(),
+            Ceiling::Clip => 0,
+        }
+    }
+
+    /// Broken samples such as those of a tempo of zero are silenced rather than passed to the output
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        let sample = if sample.is_finite() { sample } else { 0.0 };
+        let sample = match self {
+            Ceiling::Limiter(limiter) => limiter.apply(sample),
+            Ceiling::Clip => sample,
+        };
+        sample.clamp(-1.0, 1.0)
+    }
+}
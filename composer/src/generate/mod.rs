@@ -1,27 +1,120 @@
+pub mod drums;
 pub mod effects;
+pub mod flac;
+pub mod master;
 pub mod note;
+#[cfg(feature = "opus")]
+pub mod opus;
+pub mod tags;
 pub mod tones;
+#[cfg(feature = "vorbis")]
+pub mod vorbis;
+pub mod wav;
 
 use crate::parse::tone::Effect;
-use crate::parse::{Instruction, NoteLength, ToneModifier, Track};
-use effects::{Effector, EffectsQueue};
+use crate::parse::{
+    Arpeggio, ArpeggioDirection, Instruction, Metadata, NoteLength, ParsedMML, RampCurve, Swing,
+    ToneModifier,
+};
+use crate::ratio::Ratio;
+use drums::{DrumVoice, DRUM_VARIATIONS};
+use effects::{EffectChain, EffectEvent, EffectsQueue};
+use flac::{FlacOptions, FlacWriter};
+use master::{Ceiling, MasterOptions};
 use note::{Note, NotesQueue};
+#[cfg(feature = "opus")]
+use opus::{OpusOptions, OpusWriter};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Seek, Write};
 use std::sync::Arc;
+use tones::Rng;
+#[cfg(feature = "vorbis")]
+use vorbis::{VorbisOptions, VorbisWriter};
+use wav::{Quantizer, WavOptions, WavWriter};
 
 pub type FnTone = fn(f32, f32) -> f32;
+/// Oscillator which also takes a seed chosen for each note
+pub type NoiseTone = fn(u64, f32, f32) -> f32;
 
-pub fn note_length_to_float(length: &[NoteLength], default: f32) -> f32 {
+pub const TICKS_PER_WHOLE_NOTE: u64 = 192;
+
+pub fn note_length_to_ratio(length: &[NoteLength], default: Ratio) -> Ratio {
     length
         .iter()
-        .scan(0.0, |last, x| {
+        .scan(Ratio::zero(), |last, x| {
             *last = match x {
                 NoteLength::DefaultLength => default,
-                NoteLength::Dot => *last / 2.0,
-                NoteLength::Length(l) => 1.0 / (*l as f32),
+                NoteLength::Dot => *last / Ratio::from_integer(2),
+                NoteLength::Length(l) => Ratio::new(1, *l as u64),
+                NoteLength::Ticks(t) => Ratio::new(*t as u64, TICKS_PER_WHOLE_NOTE),
             };
             Some(*last)
         })
-        .sum()
+        .fold(Ratio::zero(), |sum, x| sum + x)
+}
+
+pub fn note_length_to_float(length: &[NoteLength], default: f32) -> f32 {
+    note_length_to_ratio(length, Ratio::from_f64(default as f64)).to_f32()
+}
+
+/// Returns the length of a track in whole notes and the default length after it
+pub fn measure_track(track: &[Instruction], mut default: Ratio) -> (Ratio, Ratio) {
+    let mut total = Ratio::zero();
+
+    for inst in track {
+        match inst {
+            Instruction::Note(_, length)
+            | Instruction::Chord(_, length)
+            | Instruction::Rest(length) => total = total + note_length_to_ratio(length, default),
+            Instruction::Length(length) => default = note_length_to_ratio(length, default),
+            Instruction::Repeat(track, times) => {
+                for _ in 0..*times {
+                    let (length, next_default) = measure_track(track, default);
+                    total = total + length;
+                    default = next_default;
+                }
+            }
+            Instruction::Tuplet(track, length) => {
+                total = total + note_length_to_ratio(length, default);
+                default = measure_track(track, default).1;
+            }
+            _ => (),
+        }
+    }
+
+    (total, default)
+}
+
+/// Returns how many notes a track plays, counting each note of chords and of repetitions
+pub fn count_notes(track: &[Instruction]) -> usize {
+    track
+        .iter()
+        .map(|inst| match inst {
+            Instruction::Note(..) | Instruction::PlayPCM(..) => 1,
+            Instruction::Chord(pitches, _) => pitches.len(),
+            Instruction::Repeat(track, times) => count_notes(track).saturating_mul(*times),
+            Instruction::Tuplet(track, _) => count_notes(track),
+            _ => 0,
+        })
+        .fold(0, usize::saturating_add)
+}
+
+/// Delays off-beats by moving the middle of each pair of subdivisions to `ratio`
+pub fn swing_beat(beat: Ratio, swing: &Swing) -> Ratio {
+    let pair = Ratio::new(2, swing.division as u64);
+    let ratio = Ratio::new(swing.ratio as u64, 1000);
+    let half = Ratio::new(1, 2);
+    let two = Ratio::from_integer(2);
+
+    let pairs = beat / pair;
+    let fraction = pairs.fract();
+    let swung = if fraction < half {
+        fraction * two * ratio
+    } else {
+        ratio + (fraction - half) * two * (Ratio::from_integer(1) - ratio)
+    };
+
+    (pairs.floor() + swung) * pair
 }
 
 fn partial_min<T: Copy + PartialOrd>(a: T, b: T) -> T {
@@ -40,53 +133,189 @@ fn partial_max<T: Copy + PartialOrd>(a: T, b: T) -> T {
     }
 }
 
-pub fn parse_note<'a>(length: f32, pitch: isize, state: &mut TrackState<'a>) {
+#[derive(Debug, Clone, Copy)]
+pub struct Ramp {
+    from: f32,
+    to: f32,
+    length: f32,
+    curve: RampCurve,
+}
+
+impl Ramp {
+    pub fn new(from: f32, to: f32, length: f32, curve: RampCurve) -> Self {
+        // An exponential curve cannot start or end at zero
+        let curve = if from <= 0.0 || to <= 0.0 {
+            RampCurve::Linear
+        } else {
+            curve
+        };
+
+        Ramp {
+            from,
+            to,
+            length,
+            curve,
+        }
+    }
+
+    pub fn is_over_at(&self, elapsed: f32) -> bool {
+        self.length <= elapsed
+    }
+
+    pub fn value_at(&self, elapsed: f32) -> f32 {
+        if self.is_over_at(elapsed) {
+            return self.to;
+        }
+
+        let progress = partial_max(elapsed, 0.0) / self.length;
+        match self.curve {
+            RampCurve::Linear => self.from + (self.to - self.from) * progress,
+            RampCurve::Exponential => self.from * (self.to / self.from).powf(progress),
+        }
+    }
+
+    /// Integral of 1 / value from the beginning of the ramp to `elapsed`
+    pub fn integrate_reciprocal(&self, elapsed: f32) -> f32 {
+        let ramp_end = partial_min(elapsed, self.length);
+        let after_ramp = partial_max(elapsed - self.length, 0.0) / self.to;
+
+        if ramp_end <= 0.0 {
+            return after_ramp;
+        }
+
+        let value_end = self.value_at(ramp_end);
+        let within_ramp = if self.from == self.to {
+            ramp_end / self.from
+        } else {
+            match self.curve {
+                RampCurve::Linear => {
+                    (value_end / self.from).ln() * self.length / (self.to - self.from)
+                }
+                RampCurve::Exponential => {
+                    (1.0 / self.from - 1.0 / value_end) * self.length / (self.to / self.from).ln()
+                }
+            }
+        };
+
+        within_ramp + after_ramp
+    }
+}
+
+/// Returns the start position and the volume ratio of a note with random humanization
+fn humanize(state: &mut TrackState) -> (f32, f32) {
+    let (timing, velocity) = state.humanize;
+    let position = if timing > 0.0 {
+        partial_max(state.position + timing * state.rng.next_f32(), 0.0)
+    } else {
+        state.position
+    };
+    let velocity = if velocity > 0.0 {
+        partial_max(1.0 + velocity * state.rng.next_f32(), 0.0)
+    } else {
+        1.0
+    };
+    (position, velocity)
+}
+
+pub fn parse_drum<'a>(pitch: isize, volume: f32, position: f32, state: &mut TrackState<'a>) {
+    let (voice, semitones) = DrumVoice::from_pitch(state.octave * 12 + pitch);
+    let variation = state.noise_rng.next_u64() % DRUM_VARIATIONS;
+    let sample_rate = state.sample_rate;
+
+    let hit = state
+        .drum_hits
+        .entry((voice, semitones, variation))
+        .or_insert_with(|| {
+            let tune = 2.0f32.powf(semitones as f32 / 12.0);
+            let seed = (voice as u64) << 32 | variation;
+            Arc::new(voice.render(tune, seed, sample_rate))
+        })
+        .clone();
+
+    let length = hit.len() as f32 / sample_rate;
+    let note = Note::new(
+        1.0 / length,
+        ToneKind::PCMTone(hit),
+        volume,
+        volume,
+        0.0,
+        position,
+        position + length,
+    );
+    state.notes.push(note);
+}
+
+pub fn parse_note<'a>(length: f32, pitch: isize, volume: (f32, f32), state: &mut TrackState<'a>) {
+    let (position, velocity) = humanize(state);
+    state.effects.push((position, EffectEvent::Trigger(0)));
+    if state.drum_kit {
+        parse_drum(pitch, volume.0 * velocity, position, state);
+        return;
+    }
+
+    let (volume_from, volume_to) = (volume.0 * velocity, volume.1 * velocity);
+    let note_length = length;
+    let gain = |at: f32| {
+        if note_length <= 0.0 {
+            volume_to
+        } else {
+            volume_from + (volume_to - volume_from) * partial_min(at / note_length, 1.0)
+        }
+    };
+
     for tone in &state.tones {
-        let volume = state.volume * tone.volume;
+        if tone.chain != 0 {
+            state
+                .effects
+                .push((position, EffectEvent::Trigger(tone.chain)));
+        }
+        let volume = tone.volume;
         let (attack, decay, sustain, release) = tone.envelope;
         let (unison_count, detune) = tone.detune;
         let mut frequency =
             220.0 * (2.0f32).powf((state.octave * 12 + pitch) as f32 / 12.0) * tone.tune;
         let length = partial_max(length - tone.gate, 0.0);
         for _ in 0..unison_count {
+            let tone_kind = tone.tone.reseed(&mut state.noise_rng);
             if attack != 0.0 {
                 let attack_len = partial_min(length, attack);
                 let note = Note::new(
                     frequency,
-                    tone.tone.clone(),
+                    tone_kind.clone(),
                     0.0,
-                    volume * attack_len / attack,
+                    volume * attack_len / attack * gain(attack_len),
                     0.0,
-                    state.position,
-                    state.position + attack_len,
+                    position,
+                    position + attack_len,
                 );
-                state.notes.push(note);
+                state.notes.push(note.with_chain(tone.chain));
             }
             if decay > 0.0 && length > attack {
                 let decay_len = partial_min(length - attack, decay);
                 let note = Note::new(
                     frequency,
-                    tone.tone.clone(),
-                    volume,
-                    volume - (volume - volume * sustain) * decay_len / decay,
+                    tone_kind.clone(),
+                    volume * gain(attack),
+                    (volume - (volume - volume * sustain) * decay_len / decay)
+                        * gain(attack + decay_len),
                     attack,
-                    state.position + attack,
-                    state.position + attack + decay_len,
+                    position + attack,
+                    position + attack + decay_len,
                 );
-                state.notes.push(note);
+                state.notes.push(note.with_chain(tone.chain));
             }
             if length > attack + decay {
                 let sustain_len = length - (attack + decay);
                 let note = Note::new(
                     frequency,
-                    tone.tone.clone(),
-                    volume * sustain,
-                    volume * sustain,
+                    tone_kind.clone(),
+                    volume * sustain * gain(attack + decay),
+                    volume * sustain * gain(length),
                     attack + decay,
-                    state.position + attack + decay,
-                    state.position + attack + decay + sustain_len,
+                    position + attack + decay,
+                    position + attack + decay + sustain_len,
                 );
-                state.notes.push(note);
+                state.notes.push(note.with_chain(tone.chain));
             }
             if release > 0.0 {
                 let sustain_volume = volume * sustain;
@@ -101,22 +330,79 @@ pub fn parse_note<'a>(length: f32, pitch: isize, state: &mut TrackState<'a>) {
                 let release_len = release;
                 let note = Note::new(
                     frequency,
-                    tone.tone.clone(),
-                    init_volume,
+                    tone_kind.clone(),
+                    init_volume * gain(length),
                     0.0,
                     length,
-                    state.position + length,
-                    state.position + length + release_len,
+                    position + length,
+                    position + length + release_len,
                 );
-                state.notes.push(note);
+                state.notes.push(note.with_chain(tone.chain));
             }
             frequency *= 1.0 + detune;
         }
     }
 }
 
+pub fn arpeggio_pitches(pitches: &[isize], arpeggio: &Arpeggio) -> Vec<isize> {
+    let mut sorted = pitches.to_vec();
+    sorted.sort_unstable();
+
+    let mut up: Vec<_> = (0..arpeggio.octaves as isize)
+        .flat_map(|octave| sorted.iter().map(move |pitch| pitch + octave * 12))
+        .collect();
+
+    match arpeggio.direction {
+        ArpeggioDirection::Up => up,
+        ArpeggioDirection::Down => {
+            up.reverse();
+            up
+        }
+        ArpeggioDirection::UpDown => {
+            let down: Vec<_> = up
+                .iter()
+                .rev()
+                .skip(1)
+                .take(up.len().saturating_sub(2))
+                .copied()
+                .collect();
+            up.extend(down);
+            up
+        }
+    }
+}
+
+pub fn parse_arpeggio<'a>(
+    length: Ratio,
+    pitches: &[isize],
+    arpeggio: &Arpeggio,
+    state: &mut TrackState<'a>,
+) {
+    let pitches = arpeggio_pitches(pitches, arpeggio);
+    if pitches.is_empty() {
+        state.position += state.advance(length);
+        return;
+    }
+
+    let step = Ratio::new(1, arpeggio.speed as u64);
+    let mut remaining = length;
+
+    for &pitch in pitches.iter().cycle() {
+        if remaining.is_zero() {
+            break;
+        }
+
+        let step = step.min(remaining);
+        let volume_from = state.volume;
+        let duration = state.advance(step);
+        parse_note(duration, pitch, (volume_from, state.volume), state);
+        state.position += duration;
+        remaining = remaining - step;
+    }
+}
+
 pub fn parse_play_pcm<'a>(pcm_num: usize, sample_rate: f32, state: &mut TrackState<'a>) {
-    let pcm = state.pcm_tones.get(pcm_num).cloned().unwrap_or(Arc::new(vec![0.0]));
+    let pcm = state.registry.pcm(pcm_num).unwrap_or(Arc::new(vec![0.0]));
     let length = pcm.len() as f32 / sample_rate;
     let volume = state.volume;
     let note = Note::new(
@@ -129,58 +415,135 @@ pub fn parse_play_pcm<'a>(pcm_num: usize, sample_rate: f32, state: &mut TrackSta
         state.position + length,
     );
     state.notes.push(note);
+    state.anchor.1 += length;
     state.position += length;
 }
 
 pub fn parse_instruction<'a>(inst: &Instruction, state: &mut TrackState<'a>) {
     match inst {
         Instruction::Octave(octave) => state.octave += octave,
-        Instruction::Tempo(tempo) => state.tempo = *tempo as f32,
-        Instruction::Volume(volume) => state.volume = *volume as f32,
+        Instruction::Tempo(tempo) => {
+            state.anchor = (state.beat, state.position);
+            state.tempo = *tempo as f32;
+            state.tempo_ramp = None;
+        }
+        Instruction::TempoRamp(tempo, length, curve) => {
+            let length = state.note_length(length).to_f32();
+            let ramp = Ramp::new(state.tempo, *tempo as f32, length, *curve);
+            state.anchor = (state.beat, state.position);
+            state.tempo_ramp = Some((state.beat, ramp));
+        }
+        Instruction::Volume(volume) => {
+            state.volume = *volume;
+            state.volume_ramp = None;
+        }
+        Instruction::VolumeRamp(volume, length, curve) => {
+            let length = state.note_length(length).to_f32();
+            let ramp = Ramp::new(state.volume, *volume, length, *curve);
+            state.volume_ramp = Some((state.beat, ramp));
+        }
         Instruction::Note(pitch, length) => {
-            let length = 240.0 / state.tempo * note_length_to_float(&length, state.default_length);
-            parse_note(length, *pitch, state);
+            let volume_from = state.volume;
+            let length = state.advance(note_length_to_ratio(length, state.default_length));
+            parse_note(length, *pitch, (volume_from, state.volume), state);
             state.position += length;
         }
         Instruction::Chord(pitch, length) => {
-            let length = 240.0 / state.tempo * note_length_to_float(&length, state.default_length);
+            let length = note_length_to_ratio(length, state.default_length);
+            if let Some(arpeggio) = state.arpeggio {
+                parse_arpeggio(length, pitch, &arpeggio, state);
+                return;
+            }
+
+            let volume_from = state.volume;
+            let length = state.advance(length);
             for &note in pitch {
-                parse_note(length, note, state);
+                parse_note(length, note, (volume_from, state.volume), state);
             }
             state.position += length;
         }
+        Instruction::Arpeggio(arpeggio) => state.arpeggio = *arpeggio,
+        Instruction::Swing(swing) => {
+            state.anchor = (state.beat, state.position);
+            state.swing = *swing;
+        }
+        Instruction::DrumKit(drum_kit) => state.drum_kit = *drum_kit,
+        Instruction::Seed(seed) => state.noise_rng = Rng::new(*seed),
+        Instruction::Humanize(timing, velocity, seed) => {
+            state.humanize = (*timing, *velocity);
+            if let Some(seed) = seed {
+                state.rng = Rng::new(*seed);
+            }
+        }
         Instruction::PlayPCM(pcm_num, sample_rate) => {
             parse_play_pcm(*pcm_num, *sample_rate, state);
         }
         Instruction::Rest(length) => {
-            let length = 240.0 / state.tempo * note_length_to_float(&length, state.default_length);
+            let length = state.advance(note_length_to_ratio(length, state.default_length));
             state.position += length;
         }
         Instruction::Length(length) => {
-            state.default_length = note_length_to_float(&length, state.default_length);
+            state.default_length = note_length_to_ratio(length, state.default_length);
         }
         Instruction::Repeat(track, times) => {
             for _ in 0..*times {
                 parse_track(track, state);
             }
         }
+        Instruction::Tuplet(track, length) => {
+            let length = note_length_to_ratio(length, state.default_length);
+            let (inner_length, _) = measure_track(track, state.default_length);
+            let length_scale = state.length_scale;
+            state.length_scale = length_scale * (length / inner_length);
+            parse_track(track, state);
+            state.length_scale = length_scale;
+
+            if inner_length.is_zero() {
+                state.position += state.advance(length);
+            }
+        }
+        Instruction::ToneModifier(ToneModifier::Effect(effect)) => {
+            let event = EffectEvent::Set(0, *effect);
+            state.effects.push((state.position, event));
+        }
+        Instruction::ToneModifier(ToneModifier::ClearEffects) => {
+            // The effects of the tones go along with the ones of the track
+            for chain in 0..=state.chains.len() {
+                state
+                    .effects
+                    .push((state.position, EffectEvent::Clear(chain)));
+            }
+            for effects in state.chains.iter_mut() {
+                effects.clear();
+            }
+            for tone in state.tones.iter_mut() {
+                tone.chain = 0;
+            }
+        }
         Instruction::ToneModifier(modifier) => {
-            let tones = unsafe {
-                std::slice::from_raw_parts_mut(state.tones.as_mut_ptr(), state.tones.len())
-            };
-            for tone in tones.iter_mut() {
-                tone.modify(state, modifier);
+            let mut sink = EffectSink::new(state.position, &mut state.chains, &mut state.effects);
+            for tone in state.tones.iter_mut() {
+                tone.modify(modifier, &mut state.registry, &mut sink);
             }
         }
+        Instruction::Bus(name, effects) => {
+            let bus = state.bus(name);
+            state.buses[bus].1 = Some(effects.clone());
+        }
+        Instruction::Send(name, level) => {
+            let bus = state.bus(name);
+            let event = EffectEvent::Send(bus, *level);
+            state.effects.push((state.position, event));
+        }
+        Instruction::Marker(name) => state.markers.push((name.clone(), state.position)),
+        Instruction::Metadata(_) => (),
         Instruction::Synthesize(modifiers) => {
-            state.tones = vec![Tone::new(state.fn_tones[0]); modifiers.len()];
-            let tones = unsafe {
-                std::slice::from_raw_parts_mut(state.tones.as_mut_ptr(), state.tones.len())
-            };
+            state.tones = vec![Tone::new(state.registry.oscillator(0)); modifiers.len()];
+            let mut sink = EffectSink::new(state.position, &mut state.chains, &mut state.effects);
 
-            for (tone, modifiers) in tones.iter_mut().zip(modifiers.iter()) {
+            for (tone, modifiers) in state.tones.iter_mut().zip(modifiers.iter()) {
                 for modifier in modifiers {
-                    tone.modify(state, modifier);
+                    tone.modify(modifier, &mut state.registry, &mut sink);
                 }
             }
         }
@@ -193,12 +556,28 @@ pub fn parse_track<'a>(track: &[Instruction], state: &mut TrackState<'a>) {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum ToneKind {
     FnTone(FnTone),
+    NoiseTone(NoiseTone, u64),
     PCMTone(Arc<Vec<f32>>),
 }
 
+/// Oscillators are the same if they are the same function, which the registry hands out as they
+/// are. Two copies of one function may still have different addresses.
+impl PartialEq for ToneKind {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ToneKind::FnTone(a), ToneKind::FnTone(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (ToneKind::NoiseTone(a, a_seed), ToneKind::NoiseTone(b, b_seed)) => {
+                std::ptr::fn_addr_eq(*a, *b) && a_seed == b_seed
+            }
+            (ToneKind::PCMTone(a), ToneKind::PCMTone(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Tone {
     tone: ToneKind,
@@ -207,63 +586,155 @@ pub struct Tone {
     gate: f32,
     tune: f32,
     volume: f32,
+    chain: usize,
 }
 
 impl Tone {
-    pub fn new(tone: FnTone) -> Self {
+    pub fn new(tone: ToneKind) -> Self {
         Tone {
-            tone: ToneKind::FnTone(tone),
+            tone,
             detune: (1, 0.0),
             envelope: (0.0, 0.0, 1.0, 0.0),
             gate: 0.001,
             tune: 1.0,
             volume: 1.0,
+            chain: 0,
         }
     }
 
-    pub fn modify(&mut self, state: &mut TrackState, modifier: &ToneModifier) {
+    pub fn modify(
+        &mut self,
+        modifier: &ToneModifier,
+        registry: &mut ToneRegistry,
+        effects: &mut EffectSink,
+    ) {
         match modifier {
-            ToneModifier::Tone(tone) => {
-                self.tone =
-                    ToneKind::FnTone(*state.fn_tones.get(*tone).unwrap_or(&state.fn_tones[0]))
-            }
+            ToneModifier::Tone(tone) => self.tone = registry.oscillator(*tone),
             ToneModifier::Detune(number, ratio) => self.detune = (*number, *ratio),
             ToneModifier::Envelope(a, d, s, r) => self.envelope = (*a, *d, *s, *r),
             ToneModifier::PCMTone(pcm) => {
-                self.tone = if let Some(pcm_tone) = state.pcm_tones.get(*pcm) {
-                    ToneKind::PCMTone(pcm_tone.clone())
-                } else {
-                    ToneKind::FnTone(state.fn_tones[0])
+                self.tone = match registry.pcm(*pcm) {
+                    Some(pcm_tone) => ToneKind::PCMTone(pcm_tone),
+                    None => registry.oscillator(0),
                 };
             }
             ToneModifier::Gate(gate) => self.gate = *gate,
             ToneModifier::Tune(tune) => self.tune = *tune,
             ToneModifier::Volume(volume) => self.volume = *volume,
-            ToneModifier::DefinePCMTone(pcm) => {
-                state.pcm_tones.push(Arc::new(pcm.clone()));
-            }
-            ToneModifier::Effect(effect) => {
-                let effect: Box<(dyn Effector)> = match *effect {
-                    Effect::Delay { delay, feedback } => {
-                        Box::new(effects::Delay::new(delay, feedback, state.sample_rate))
-                    }
-                    Effect::LowPassFilter { cut_off } => Box::new(
-                        effects::LowPassFilter::new(cut_off, state.sample_rate),
-                    ),
-                    Effect::HighPassFilter { cut_off } => Box::new(
-                        effects::HighPassFilter::new(cut_off, state.sample_rate),
-                    ),
-                };
-                state.effects.push((state.position, effect));
+            ToneModifier::DefinePCMTone(pcm) => registry.define_pcm(pcm.clone()),
+            ToneModifier::Effect(effect) => self.chain = effects.chain_with(self.chain, effect),
+            ToneModifier::ClearEffects => self.chain = 0,
+        }
+    }
+}
+
+/// Oscillators and PCM waves which tones can be switched to
+pub struct ToneRegistry<'a> {
+    oscillators: &'a [ToneKind],
+    pcm_tones: Vec<Arc<Vec<f32>>>,
+}
+
+impl<'a> ToneRegistry<'a> {
+    pub fn new(oscillators: &'a [ToneKind], pcm_tones: Vec<Arc<Vec<f32>>>) -> Self {
+        ToneRegistry {
+            oscillators,
+            pcm_tones,
+        }
+    }
+
+    /// The number itself if the oscillator exists, or otherwise 0
+    pub fn oscillator_index(&self, number: usize) -> usize {
+        if number < self.oscillators.len() {
+            number
+        } else {
+            0
+        }
+    }
+
+    /// The oscillator of the number, or the first one if it does not exist
+    pub fn oscillator(&self, number: usize) -> ToneKind {
+        self.oscillators[self.oscillator_index(number)].clone()
+    }
+
+    pub fn pcm(&self, number: usize) -> Option<Arc<Vec<f32>>> {
+        self.pcm_tones.get(number).cloned()
+    }
+
+    pub fn define_pcm(&mut self, pcm: Vec<f32>) {
+        self.pcm_tones.push(Arc::new(pcm));
+    }
+}
+
+/// Receives changes of effect chains, which happen at the current position of the track.
+/// `chains` holds the effects of each chain of the tones, where chain `n` is at `n - 1`.
+pub struct EffectSink<'s> {
+    position: f32,
+    chains: &'s mut Vec<Vec<Effect>>,
+    effects: &'s mut Vec<(f32, EffectEvent)>,
+}
+
+impl<'s> EffectSink<'s> {
+    pub fn new(
+        position: f32,
+        chains: &'s mut Vec<Vec<Effect>>,
+        effects: &'s mut Vec<(f32, EffectEvent)>,
+    ) -> Self {
+        EffectSink {
+            position,
+            chains,
+            effects,
+        }
+    }
+
+    /// Chain with the effects of the chain and the effect set on them. The effects of a chain
+    /// change only when `@F0` clears them, so tones with the same effects share one chain.
+    pub fn chain_with(&mut self, chain: usize, effect: &Effect) -> usize {
+        let mut effects = match chain.checked_sub(1) {
+            Some(index) => self.chains[index].clone(),
+            None => Vec::new(),
+        };
+        match effects.iter_mut().find(|current| current.same_kind(effect)) {
+            Some(current) => *current = *effect,
+            None => effects.push(*effect),
+        }
+
+        if let Some(index) = self.chains.iter().position(|x| *x == effects) {
+            return index + 1;
+        }
+        // Chains emptied by `@F0` are taken before adding one
+        let index = match self.chains.iter().position(Vec::is_empty) {
+            Some(index) => index,
+            None => {
+                self.chains.push(Vec::new());
+                self.chains.len() - 1
             }
+        };
+        for &effect in &effects {
+            self.push(EffectEvent::Set(index + 1, effect));
         }
+        self.chains[index] = effects;
+        index + 1
+    }
+
+    pub fn push(&mut self, event: EffectEvent) {
+        self.effects.push((self.position, event));
     }
 }
 
 impl ToneKind {
+    /// Gives noise a new seed so that every note sounds different
+    pub fn reseed(&self, rng: &mut Rng) -> Self {
+        match self {
+            ToneKind::NoiseTone(tone, _) => ToneKind::NoiseTone(*tone, rng.next_u64()),
+            tone => tone.clone(),
+        }
+    }
+
     pub fn sample(&self, frequency: f32, position: f32) -> f32 {
         match self {
             ToneKind::FnTone(tone) => tone(frequency, position),
+            ToneKind::NoiseTone(tone, seed) => tone(*seed, frequency, position),
+            ToneKind::PCMTone(tone) if tone.is_empty() => 0.0,
             ToneKind::PCMTone(tone) => {
                 let len = tone.len() as f32;
                 let index = ((frequency * position * len) % len) as usize;
@@ -275,41 +746,153 @@ impl ToneKind {
 
 pub struct TrackState<'a> {
     sample_rate: f32,
-    effects: Vec<(f32, Box<dyn Effector>)>,
+    effects: Vec<(f32, EffectEvent)>,
+    /// Effects of the chains of the tones
+    chains: Vec<Vec<Effect>>,
     notes: Vec<Note>,
     position: f32,
+    beat: Ratio,
+    anchor: (Ratio, f32),
+    length_scale: Ratio,
     tempo: f32,
-    default_length: f32,
+    tempo_ramp: Option<(Ratio, Ramp)>,
+    swing: Option<Swing>,
+    humanize: (f32, f32),
+    rng: Rng,
+    default_length: Ratio,
     volume: f32,
+    volume_ramp: Option<(Ratio, Ramp)>,
     tones: Vec<Tone>,
-    fn_tones: &'a [FnTone],
+    registry: ToneRegistry<'a>,
     octave: isize,
-    pcm_tones: Vec<Arc<Vec<f32>>>,
+    arpeggio: Option<Arpeggio>,
+    drum_kit: bool,
+    noise_rng: Rng,
+    drum_hits: HashMap<(DrumVoice, isize, u64), Arc<Vec<f32>>>,
+    /// Names and effects of the buses, which are shared by all tracks.
+    /// The effects are `None` until `@B` declares the bus.
+    buses: Vec<(String, Option<Vec<Effect>>)>,
+    /// Names and positions of the markers in all tracks
+    markers: Vec<(String, f32)>,
 }
 
 impl<'a> TrackState<'a> {
-    pub fn new(sample_rate: f32, fn_tones: &'a [FnTone], pcm_tones: Vec<Arc<Vec<f32>>>) -> Self {
+    pub fn new(
+        sample_rate: f32,
+        oscillators: &'a [ToneKind],
+        pcm_tones: Vec<Arc<Vec<f32>>>,
+    ) -> Self {
         Self {
             sample_rate,
             effects: Vec::new(),
+            chains: Vec::new(),
             notes: Vec::new(),
             position: 0.0,
+            beat: Ratio::zero(),
+            anchor: (Ratio::zero(), 0.0),
+            length_scale: Ratio::from_integer(1),
             tempo: 120.0,
-            default_length: 1.0 / 8.0,
+            tempo_ramp: None,
+            swing: None,
+            humanize: (0.0, 0.0),
+            rng: Rng::new(HUMANIZE_SEED),
+            default_length: Ratio::new(1, 8),
             volume: 1.0,
-            tones: vec![Tone::new(fn_tones[0])],
-            fn_tones,
+            volume_ramp: None,
+            tones: vec![Tone::new(oscillators[0].clone())],
+            registry: ToneRegistry::new(oscillators, pcm_tones),
             octave: 0,
-            pcm_tones,
+            arpeggio: None,
+            drum_kit: false,
+            noise_rng: Rng::new(NOISE_SEED),
+            drum_hits: HashMap::new(),
+            buses: Vec::new(),
+            markers: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
+        if let Some((_, ramp)) = self.tempo_ramp.take() {
+            self.tempo = ramp.to;
+        }
+        self.swing = None;
+        self.humanize = (0.0, 0.0);
         self.position = 0.0;
-        self.default_length = 1.0 / 8.0;
+        self.beat = Ratio::zero();
+        self.anchor = (Ratio::zero(), 0.0);
+        self.length_scale = Ratio::from_integer(1);
+        self.default_length = Ratio::new(1, 8);
         self.volume = 1.0;
-        self.tones = vec![Tone::new(self.fn_tones[0])];
+        self.volume_ramp = None;
+        self.tones = vec![Tone::new(self.registry.oscillator(0))];
         self.octave = 0;
+        self.arpeggio = None;
+        self.drum_kit = false;
+    }
+
+    /// Index of the bus of the name, which is added if it does not exist yet
+    pub fn bus(&mut self, name: &str) -> usize {
+        match self.buses.iter().position(|(bus, _)| bus == name) {
+            Some(index) => index,
+            None => {
+                self.buses.push((name.to_string(), None));
+                self.buses.len() - 1
+            }
+        }
+    }
+
+    /// Applies the default length and the tuplet scale to a note length
+    pub fn note_length(&self, length: &[NoteLength]) -> Ratio {
+        note_length_to_ratio(length, self.default_length) * self.length_scale
+    }
+
+    fn swung(&self, beat: Ratio) -> Ratio {
+        match &self.swing {
+            Some(swing) => swing_beat(beat, swing),
+            None => beat,
+        }
+    }
+
+    fn seconds_at(&self, beat: Ratio) -> f32 {
+        let (anchor_beat, anchor_seconds) = self.anchor;
+        let (from, to) = (self.swung(anchor_beat), self.swung(beat));
+
+        let beats = match &self.tempo_ramp {
+            Some((ramp_start, ramp)) => {
+                let ramp_start = self.swung(*ramp_start);
+                ramp.integrate_reciprocal(to.saturating_sub(ramp_start).to_f32())
+                    - ramp.integrate_reciprocal(from.saturating_sub(ramp_start).to_f32())
+            }
+            None => to.saturating_sub(from).to_f32() / self.tempo,
+        };
+
+        anchor_seconds + 240.0 * beats
+    }
+
+    /// Moves forward by a length in whole notes and returns how many seconds it takes
+    pub fn advance(&mut self, length: Ratio) -> f32 {
+        self.beat = self.beat + length * self.length_scale;
+        let position = self.seconds_at(self.beat);
+
+        if let Some((ramp_start, ramp)) = &self.tempo_ramp {
+            let ramp_start = self.swung(*ramp_start);
+            let elapsed = self.swung(self.beat).saturating_sub(ramp_start).to_f32();
+            self.tempo = ramp.value_at(elapsed);
+            if ramp.is_over_at(elapsed) {
+                self.anchor = (self.beat, position);
+                self.tempo_ramp = None;
+            }
+        }
+
+        if let Some((ramp_start, ramp)) = &self.volume_ramp {
+            let elapsed = self.beat.saturating_sub(*ramp_start).to_f32();
+            self.volume = ramp.value_at(elapsed);
+            if ramp.is_over_at(elapsed) {
+                self.volume_ramp = None;
+            }
+        }
+
+        position - self.position
     }
 
     pub fn push_note(&mut self, note: Note) {
@@ -321,46 +904,67 @@ impl<'a> TrackState<'a> {
     }
 
     pub fn drain_effects_queue(&mut self) -> EffectsQueue {
+        self.chains.clear();
         EffectsQueue::new(self.effects.split_off(0))
     }
 }
 
-fn u16_to_bytes(value: u16) -> impl Iterator<Item = u8> {
-    (0..2).map(move |x| (value >> (x * 8)) as u8)
-}
-
-fn i16_to_bytes(value: i16) -> impl Iterator<Item = u8> {
-    (0..2).map(move |x| (value >> (x * 8)) as u8)
-}
-
-fn u32_to_bytes(value: u32) -> impl Iterator<Item = u8> {
-    (0..4).map(move |x| (value >> (x * 8)) as u8)
-}
-
 #[derive(Debug)]
 pub struct Generator {
     sample_rate: f32,
+    /// Samples mixed, which is ahead of `emitted` by the latency of the ceiling
     position: usize,
+    emitted: usize,
+    master: MasterOptions,
+    ceiling: Ceiling,
     notes_queues: Vec<NotesQueue>,
     effects_queues: Vec<EffectsQueue>,
     ringing_notes: Vec<Vec<Note>>,
-    applied_effects: Vec<Vec<Box<dyn Effector>>>,
+    effect_chains: Vec<Vec<EffectChain>>,
+    chain_samples: Vec<f32>,
+    /// Levels sent from each track to each bus
+    sends: Vec<Vec<f32>>,
+    /// Buses which no `@B` declares are left out, so that a send to them is not the dry sound again
+    buses: Vec<Option<EffectChain>>,
+    bus_samples: Vec<f32>,
+    muted: Vec<bool>,
+    soloed: Vec<bool>,
+    metadata: Metadata,
     track_length: f32,
+    /// Sorted by position
+    markers: Vec<(String, f32)>,
 }
 
-static TONES: &[FnTone] = &[
-    tones::pulse50,
-    tones::pulse25,
-    tones::pulse125,
-    tones::triangle,
-    tones::saw,
-    tones::sine,
-    tones::noise,
+const HUMANIZE_SEED: u64 = 88172645463325252;
+const NOISE_SEED: u64 = 12345;
+
+/// Seconds mixed before the position seeked to, so that delays and reverbs ring into it
+const SEEK_PREROLL: f32 = 2.0;
+
+static TONES: &[ToneKind] = &[
+    ToneKind::FnTone(tones::pulse50),
+    ToneKind::FnTone(tones::pulse25),
+    ToneKind::FnTone(tones::pulse125),
+    ToneKind::FnTone(tones::triangle),
+    ToneKind::FnTone(tones::saw),
+    ToneKind::FnTone(tones::sine),
+    ToneKind::NoiseTone(tones::noise, 0),
+    ToneKind::NoiseTone(tones::pitched_noise, 0),
+    ToneKind::NoiseTone(tones::lfsr_long, 0),
+    ToneKind::NoiseTone(tones::lfsr_short, 0),
 ];
 
 impl Generator {
-    pub fn new(sample_rate: f32, tracks: &[Track]) -> Self {
+    pub fn new(sample_rate: f32, mml: &ParsedMML) -> Self {
+        Self::with_master(sample_rate, mml, MasterOptions::default())
+    }
+
+    pub fn with_master(sample_rate: f32, mml: &ParsedMML, master: MasterOptions) -> Self {
+        let tracks = &mml.tracks;
         let mut state = TrackState::new(sample_rate, TONES, Vec::new());
+        if let Some(tempo) = mml.metadata.tempo {
+            state.tempo = tempo as f32;
+        }
         let (notes_queues, effects_queues): (Vec<_>, Vec<_>) = tracks
             .iter()
             .map(|track| {
@@ -370,101 +974,291 @@ impl Generator {
             })
             .unzip();
 
+        let buses = state
+            .buses
+            .iter()
+            .map(|(_, effects)| {
+                let mut chain = EffectChain::default();
+                for effect in effects.as_ref()? {
+                    chain.set(effect, sample_rate);
+                }
+                Some(chain)
+            })
+            .collect();
+
         let track_length = notes_queues
             .iter()
             .flat_map(|queue| queue.iter().map(|note| note.end_at()))
             .fold(0.0, partial_max);
 
+        let mut markers = state.markers;
+        markers.sort_by(|a, b| a.1.total_cmp(&b.1));
+
         Self {
             sample_rate,
             position: 0,
+            emitted: 0,
+            master,
+            ceiling: Ceiling::new(&master, sample_rate),
             notes_queues,
             effects_queues,
             ringing_notes: vec![Vec::new(); tracks.len()],
-            applied_effects: (0..(tracks.len())).map(|_| Vec::new()).collect(),
+            effect_chains: (0..(tracks.len())).map(|_| Vec::new()).collect(),
+            chain_samples: Vec::new(),
+            sends: vec![Vec::new(); tracks.len()],
+            buses,
+            bus_samples: Vec::new(),
+            muted: vec![false; tracks.len()],
+            soloed: vec![false; tracks.len()],
+            metadata: mml.metadata.clone(),
             track_length,
+            markers,
         }
     }
 
     pub fn is_over(&self) -> bool {
-        self.track_length + 1.0 <= self.position as f32 / self.sample_rate
+        self.track_length + 1.0 <= self.emitted as f32 / self.sample_rate
     }
 
     pub fn track_length(&self) -> f32 {
         self.track_length
     }
 
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn tracks(&self) -> usize {
+        self.notes_queues.len()
+    }
+
+    /// Names and positions in seconds of the markers, sorted by position
+    pub fn markers(&self) -> &[(String, f32)] {
+        &self.markers
+    }
+
+    /// Position in seconds of the first marker of the name
+    pub fn marker(&self, name: &str) -> Option<f32> {
+        self.markers
+            .iter()
+            .find(|(marker, _)| marker == name)
+            .map(|&(_, position)| position)
+    }
+
+    /// Moves forward to the position in seconds. Notes and effect changes before it are taken
+    /// without being synthesized, except for the last seconds which are mixed and thrown away
+    /// so that delays, reverbs and the limiter carry what rings into the position.
+    /// It cannot move backwards.
+    pub fn seek(&mut self, seconds: f32) {
+        let target = (seconds.max(0.0) * self.sample_rate).round() as usize;
+        let skipped = target.saturating_sub((SEEK_PREROLL * self.sample_rate) as usize);
+        if self.position < skipped {
+            self.skip_to(skipped);
+        }
+        while self.emitted < target && self.next().is_some() {}
+    }
+
+    /// Muted tracks are still processed, so they can be unmuted in the middle of the song.
+    /// Tracks which do not exist are ignored.
+    pub fn set_muted(&mut self, track: usize, muted: bool) {
+        if let Some(x) = self.muted.get_mut(track) {
+            *x = muted;
+        }
+    }
+
+    /// While any track is soloed, only the soloed tracks are heard
+    pub fn set_soloed(&mut self, track: usize, soloed: bool) {
+        if let Some(x) = self.soloed.get_mut(track) {
+            *x = soloed;
+        }
+    }
+
+    pub fn is_audible(&self, track: usize) -> bool {
+        let solo = self.soloed.contains(&true);
+        match (self.muted.get(track), self.soloed.get(track)) {
+            (Some(&muted), Some(&soloed)) => !muted && (soloed || !solo),
+            _ => false,
+        }
+    }
+
+    /// Generators which each play only one track, sent to its buses as in the whole song
+    pub fn stems(sample_rate: f32, mml: &ParsedMML, master: MasterOptions) -> Vec<Self> {
+        (0..mml.tracks.len())
+            .map(|track| {
+                let mut generator = Self::with_master(sample_rate, mml, master);
+                generator.set_soloed(track, true);
+                generator
+            })
+            .collect()
+    }
+
+    /// Renders the whole song, normalizing it if the master options ask to
+    pub fn render(mut self) -> Vec<f32> {
+        let normalization = match self.master.normalization {
+            Some(normalization) => normalization,
+            None => return self.collect(),
+        };
+
+        let mut samples = Vec::new();
+        while !self.is_over() {
+            samples.push(self.mix());
+            self.emitted += 1;
+        }
+        master::normalize(&mut samples, normalization, self.sample_rate);
+
+        let latency = self.ceiling.latency();
+        let ceiling = &mut self.ceiling;
+        let padding = std::iter::repeat_n(0.0, latency);
+        samples
+            .into_iter()
+            .chain(padding)
+            .map(|sample| ceiling.apply(sample))
+            .skip(latency)
+            .collect()
+    }
+
+    /// Streams the samples with dither. Normalization is not applied as it needs the whole song.
     pub fn into_i16_stream(self) -> impl Iterator<Item = i16> {
-        self.map(|sample| (sample * 32767.0) as i16)
-    }
-
-    pub fn into_riff(self) -> Vec<u8> {
-        let sample_rate = self.sample_rate as u32;
-        let channels = 1;
-        let bits_per_sample = 16;
-        let block_align = channels * bits_per_sample / 8u16;
-        let byte_rate = sample_rate * block_align as u32;
-
-        let mut riff: Vec<_> = std::iter::empty()
-            // RIFF Chunk
-            .chain(b"RIFF".iter().copied())
-            .chain(u32_to_bytes(0)) // Chunk Size: Overwrite later
-            .chain(b"WAVE".iter().copied())
-            // Format Subchunk
-            .chain(b"fmt ".iter().copied()) // Subchunk ID
-            .chain(u32_to_bytes(16)) // Subchunk Size
-            .chain(u16_to_bytes(1)) // PCM
-            .chain(u16_to_bytes(channels as u16))
-            .chain(u32_to_bytes(sample_rate))
-            .chain(u32_to_bytes(byte_rate))
-            .chain(u16_to_bytes(block_align))
-            .chain(u16_to_bytes(bits_per_sample))
-            .chain(b"data".iter().copied())
-            .chain(u32_to_bytes(0)) // Data Size: Overwrite later
-            .chain(self.into_i16_stream().flat_map(i16_to_bytes))
-            .collect();
+        let mut quantizer = Quantizer::new(16, true);
+        self.map(move |sample| quantizer.quantize(sample) as i16)
+    }
 
-        for (i, byte) in (4..=7).zip(u32_to_bytes(riff.len() as u32)) {
-            riff[i] = byte;
+    /// Normalized samples if the master options ask for them, or otherwise the streamed samples
+    fn into_samples(self) -> Box<dyn Iterator<Item = f32>> {
+        match self.master.normalization {
+            Some(_) => Box::new(self.render().into_iter()),
+            None => Box::new(self),
         }
+    }
 
-        for (i, byte) in (40..=43).zip(u32_to_bytes(riff.len() as u32 - 44)) {
-            riff[i] = byte;
+    /// Writes the song as a WAV file. Samples are streamed to the writer unless they are normalized.
+    pub fn write_wav<W: Write + Seek>(self, writer: W, options: WavOptions) -> io::Result<W> {
+        let metadata = self.metadata.clone();
+        let mut wav = WavWriter::new(writer, self.sample_rate as u32, options, &metadata)?;
+        for sample in self.into_samples() {
+            wav.write_sample(sample)?;
         }
+        wav.finish()
+    }
 
-        riff
+    /// Writes the song as a FLAC file, streaming as `write_wav` does
+    pub fn write_flac<W: Write + Seek>(self, writer: W, options: FlacOptions) -> io::Result<W> {
+        let metadata = self.metadata.clone();
+        let mut flac = FlacWriter::new(writer, self.sample_rate as u32, options, &metadata)?;
+        for sample in self.into_samples() {
+            flac.write_sample(sample)?;
+        }
+        flac.finish()
     }
-}
 
-impl Iterator for Generator {
-    type Item = f32;
+    /// Writes the song as an Ogg Opus file, which needs a sample rate of 8, 12, 16, 24 or 48 kHz
+    #[cfg(feature = "opus")]
+    pub fn write_opus<W: Write>(self, writer: W, options: OpusOptions) -> io::Result<W> {
+        let metadata = self.metadata.clone();
+        let mut opus = OpusWriter::new(writer, self.sample_rate as u32, options, &metadata)?;
+        for sample in self.into_samples() {
+            opus.write_sample(sample)?;
+        }
+        opus.finish()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.is_over() {
-            return None;
+    /// Writes the song as an Ogg Vorbis file
+    #[cfg(feature = "vorbis")]
+    pub fn write_vorbis<W: Write>(self, writer: W, options: VorbisOptions) -> io::Result<W> {
+        let metadata = self.metadata.clone();
+        let mut vorbis = VorbisWriter::new(writer, self.sample_rate as u32, options, &metadata)?;
+        for sample in self.into_samples() {
+            vorbis.write_sample(sample)?;
         }
+        vorbis.finish()
+    }
 
-        let mut sample = 0.0;
-        let position = self.position as f32 / self.sample_rate;
+    /// WAV file of the song, which fails only if the song is too long for a WAV file
+    pub fn into_riff_with(self, options: WavOptions) -> io::Result<Vec<u8>> {
+        self.write_wav(Cursor::new(Vec::new()), options)
+            .map(Cursor::into_inner)
+    }
+
+    /// 16-bit PCM WAV file of the song
+    pub fn into_riff(self) -> io::Result<Vec<u8>> {
+        self.into_riff_with(WavOptions::default())
+    }
+}
+
+/// Effect chain of the index, which is added if the track does not have it yet
+fn chain_at(chains: &mut Vec<EffectChain>, index: usize) -> &mut EffectChain {
+    if chains.len() <= index {
+        chains.resize_with(index + 1, EffectChain::default);
+    }
+    &mut chains[index]
+}
 
+impl Generator {
+    fn apply_effect_events(&mut self, position: f32) {
         let zipped = self
             .effects_queues
             .iter_mut()
-            .zip(self.applied_effects.iter_mut());
+            .zip(self.effect_chains.iter_mut())
+            .zip(self.sends.iter_mut());
 
-        for (effects_queue, applied_effects) in zipped {
-            while let Some(note) = effects_queue.next_before(position) {
-                applied_effects.push(note);
+        for ((effects_queue, effect_chains), sends) in zipped {
+            while let Some(event) = effects_queue.next_before(position) {
+                match event {
+                    EffectEvent::Set(chain, effect) => {
+                        chain_at(effect_chains, chain).set(&effect, self.sample_rate)
+                    }
+                    EffectEvent::Clear(chain) => chain_at(effect_chains, chain).clear(),
+                    EffectEvent::Trigger(chain) => chain_at(effect_chains, chain).trigger(),
+                    EffectEvent::Send(bus, level) => {
+                        if sends.len() <= bus {
+                            sends.resize(bus + 1, 0.0);
+                        }
+                        sends[bus] = level;
+                    }
+                }
             }
         }
+    }
+
+    /// Takes the notes and the effect changes until the sample without mixing them.
+    /// The ceiling still holds samples from before, which the pre-roll of `seek` pushes out.
+    fn skip_to(&mut self, position: usize) {
+        self.position = position;
+        self.emitted = position;
+        let position = position as f32 / self.sample_rate;
+        self.apply_effect_events(position);
+
+        let zipped = self
+            .notes_queues
+            .iter_mut()
+            .zip(self.ringing_notes.iter_mut());
+        for (notes_queue, ringing_notes) in zipped {
+            while let Some(note) = notes_queue.next_before(position) {
+                ringing_notes.push(note);
+            }
+            ringing_notes.retain(|note| !note.is_over(position));
+        }
+    }
+
+    /// Sum of the tracks at the next sample before the ceiling
+    fn mix(&mut self) -> f32 {
+        let mut sample = 0.0;
+        let position = self.position as f32 / self.sample_rate;
+        let solo = self.soloed.contains(&true);
+        self.apply_effect_events(position);
+
+        self.bus_samples.clear();
+        self.bus_samples.resize(self.buses.len(), 0.0);
 
         let zipped = self
             .notes_queues
             .iter_mut()
             .zip(self.ringing_notes.iter_mut())
-            .zip(self.applied_effects.iter_mut());
+            .zip(self.effect_chains.iter_mut())
+            .zip(self.sends.iter())
+            .zip(self.muted.iter().zip(self.soloed.iter()));
 
-        for ((notes_queue, ringing_notes), applied_effects) in zipped {
+        for ((((notes_queue, ringing_notes), effect_chains), sends), (&muted, &soloed)) in zipped {
             while let Some(note) = notes_queue.next_before(position) {
                 ringing_notes.push(note);
             }
@@ -476,22 +1270,67 @@ impl Iterator for Generator {
                     cursor += 1;
                 }
             }
-            let mut track_sample = 0.0;
+            let chain_samples = &mut self.chain_samples;
+            chain_samples.clear();
+            chain_samples.resize(effect_chains.len().max(1), 0.0);
             for note in ringing_notes {
-                track_sample += note.get_sample(position);
+                match chain_samples.get_mut(note.chain()) {
+                    Some(chain_sample) => *chain_sample += note.get_sample(position),
+                    None => chain_samples[0] += note.get_sample(position),
+                }
             }
 
-            for effect in applied_effects {
-                track_sample = effect.apply(track_sample);
+            // Tones with their own effects are mixed into the track before the effects of the track
+            let mut track_sample = chain_samples[0];
+            let tone_chains = effect_chains.iter_mut().zip(chain_samples.iter()).skip(1);
+            for (chain, &chain_sample) in tone_chains {
+                // Chains of tones which have stopped ringing cost nothing once their tails die out
+                if chain_sample == 0.0 && chain.is_idle() {
+                    continue;
+                }
+                track_sample += chain.apply(chain_sample);
+            }
+            if let Some(chain) = effect_chains.first_mut() {
+                track_sample = chain.apply(track_sample);
+            }
+            if muted || (solo && !soloed) {
+                continue;
             }
 
+            for (bus_sample, level) in self.bus_samples.iter_mut().zip(sends) {
+                *bus_sample += track_sample * level;
+            }
             sample += track_sample
         }
 
+        for (bus, &bus_sample) in self.buses.iter_mut().zip(self.bus_samples.iter()) {
+            if let Some(bus) = bus {
+                sample += bus.apply(bus_sample);
+            }
+        }
+
         self.position += 1;
 
-        sample = partial_max(-1.0, partial_min(sample / 4.0, 1.0));
+        sample * self.master.gain
+    }
+}
+
+impl Iterator for Generator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_over() {
+            return None;
+        }
+
+        // The limiter looks ahead, so the first samples are fed to it before any output
+        while self.position < self.emitted + self.ceiling.latency() {
+            let sample = self.mix();
+            self.ceiling.apply(sample);
+        }
 
-        Some(sample)
+        let sample = self.mix();
+        self.emitted += 1;
+        Some(self.ceiling.apply(sample))
     }
 }
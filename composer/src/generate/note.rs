@@ -9,6 +9,7 @@ pub struct Note {
     offset: f32,
     start_at: f32,
     end_at: f32,
+    chain: usize,
 }
 
 impl Note {
@@ -41,6 +42,15 @@ impl Note {
         self.end_at
     }
 
+    /// Effect chain the note is played through
+    pub fn chain(&self) -> usize {
+        self.chain
+    }
+
+    pub fn with_chain(self, chain: usize) -> Self {
+        Self { chain, ..self }
+    }
+
     pub fn new(
         frequency: f32,
         tone: ToneKind,
@@ -58,6 +68,7 @@ impl Note {
             offset,
             start_at,
             end_at,
+            chain: 0,
         }
     }
 }
@@ -69,11 +80,7 @@ pub struct NotesQueue {
 
 impl NotesQueue {
     pub fn new(mut notes: Vec<Note>) -> Self {
-        notes.sort_unstable_by(|a, b| {
-            b.start_at
-                .partial_cmp(&a.start_at)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+        notes.sort_unstable_by(|a, b| b.start_at.total_cmp(&a.start_at));
         NotesQueue { notes }
     }
 
@@ -85,7 +92,7 @@ impl NotesQueue {
         Some(self.notes.remove(self.notes.len() - 1))
     }
 
-    pub fn iter(&self) -> impl Iterator<Item=&Note> {
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
         self.notes.iter()
     }
 
@@ -0,0 +1,144 @@
+use crate::generate::tags::vorbis_comments;
+use crate::parse::Metadata;
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Granule positions of Ogg Opus count samples at 48 kHz whatever the sample rate is
+const GRANULE_RATE: u32 = 48000;
+const FRAMES_PER_SECOND: u32 = 50;
+/// The largest packet recommended by libopus
+const MAX_PACKET: usize = 4000;
+const SERIAL: u32 = 0x636f_6d70;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusOptions {
+    /// Bits per second
+    pub bitrate: i32,
+}
+
+impl Default for OpusOptions {
+    fn default() -> Self {
+        OpusOptions { bitrate: 64000 }
+    }
+}
+
+fn opus_error(error: audiopus::Error) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+/// Writes a mono Ogg Opus file in frames of 20 milliseconds
+pub struct OpusWriter<W: Write> {
+    packets: PacketWriter<W>,
+    encoder: Encoder,
+    frame: Vec<f32>,
+    frame_size: usize,
+    /// Granule positions per sample
+    granule_scale: u64,
+    /// Granule position at the end of the last encoded frame
+    granule: u64,
+    pre_skip: u64,
+    samples: u64,
+    /// The last packet is held back until it is known whether it ends the stream
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl<W: Write> OpusWriter<W> {
+    pub fn new(
+        writer: W,
+        sample_rate: u32,
+        options: OpusOptions,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let rate = SampleRate::try_from(sample_rate as i32).map_err(|_| {
+            let message = "Opus supports only sample rates of 8, 12, 16, 24 and 48 kHz";
+            io::Error::new(io::ErrorKind::InvalidInput, message)
+        })?;
+        let mut encoder =
+            Encoder::new(rate, Channels::Mono, Application::Audio).map_err(opus_error)?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(options.bitrate))
+            .map_err(opus_error)?;
+
+        // The decoder drops as many samples as the encoder delays
+        let granule_scale = (GRANULE_RATE / sample_rate) as u64;
+        let pre_skip = encoder.lookahead().map_err(opus_error)? as u64 * granule_scale;
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // Version
+        head.push(1); // Channels
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output Gain
+        head.push(0); // Mono or stereo without a mapping table
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend(vorbis_comments(metadata));
+
+        // Each header is on its own page
+        let mut packets = PacketWriter::new(writer);
+        let end_page = PacketWriteEndInfo::EndPage;
+        packets.write_packet(head.into_boxed_slice(), SERIAL, end_page, 0)?;
+        packets.write_packet(tags.into_boxed_slice(), SERIAL, end_page, 0)?;
+
+        let frame_size = (sample_rate / FRAMES_PER_SECOND) as usize;
+        Ok(OpusWriter {
+            packets,
+            encoder,
+            frame: Vec::with_capacity(frame_size),
+            frame_size,
+            granule_scale,
+            granule: 0,
+            pre_skip,
+            samples: 0,
+            pending: None,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.frame.push(sample);
+        self.samples += 1;
+        if self.frame.len() == self.frame_size {
+            self.encode_frame()?;
+        }
+        Ok(())
+    }
+
+    fn encode_frame(&mut self) -> io::Result<()> {
+        let mut packet = vec![0; MAX_PACKET];
+        let size = self
+            .encoder
+            .encode_float(&self.frame, &mut packet)
+            .map_err(opus_error)?;
+        packet.truncate(size);
+        self.frame.clear();
+        self.granule += self.frame_size as u64 * self.granule_scale;
+
+        if let Some((packet, granule)) = self.pending.replace((packet, self.granule)) {
+            let info = PacketWriteEndInfo::NormalPacket;
+            self.packets
+                .write_packet(packet.into_boxed_slice(), SERIAL, info, granule)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes silence until the delayed samples come out, and returns the writer at the end of the file
+    pub fn finish(mut self) -> io::Result<W> {
+        // The granule position of the last page tells where the song ends within the last frame
+        let end = self.pre_skip + self.samples * self.granule_scale;
+        while self.granule < end || self.pending.is_none() {
+            self.frame.resize(self.frame_size, 0.0);
+            self.encode_frame()?;
+        }
+
+        if let Some((packet, _)) = self.pending.take() {
+            let info = PacketWriteEndInfo::EndStream;
+            self.packets
+                .write_packet(packet.into_boxed_slice(), SERIAL, info, end)?;
+        }
+        let mut writer = self.packets.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
@@ -0,0 +1,52 @@
+use crate::parse::Metadata;
+
+/// Texts of the metadata which are given, with the IDs of the title and the composer
+fn fields<'a>(metadata: &'a Metadata, ids: [&'a [u8; 4]; 2]) -> Vec<(&'a [u8; 4], &'a str)> {
+    let texts = [&metadata.title, &metadata.composer];
+    ids.iter()
+        .zip(texts.iter())
+        .filter_map(|(&id, text)| Some((id, text.as_deref()?)))
+        .collect()
+}
+
+/// `LIST` chunk of a WAV file with the `INFO` of the song, or nothing if there is no information
+pub fn riff_info(metadata: &Metadata) -> Vec<u8> {
+    let mut info = b"INFO".to_vec();
+    for (id, text) in fields(metadata, [b"INAM", b"IART"]) {
+        // Strings are terminated with null, and chunks are padded to even sizes
+        let size = text.len() + 1;
+        info.extend_from_slice(id);
+        info.extend_from_slice(&(size as u32).to_le_bytes());
+        info.extend_from_slice(text.as_bytes());
+        info.resize(info.len() + 1 + size % 2, 0);
+    }
+    if info.len() == 4 {
+        return Vec::new();
+    }
+
+    let mut list = b"LIST".to_vec();
+    list.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    list.extend(info);
+    list
+}
+
+/// Vorbis comments with the `TITLE` and the `ARTIST`, as FLAC, Ogg Vorbis and Ogg Opus files store them
+pub fn vorbis_comments(metadata: &Metadata) -> Vec<u8> {
+    const VENDOR: &[u8] = b"composer";
+
+    let texts = [("TITLE", &metadata.title), ("ARTIST", &metadata.composer)];
+    let comments: Vec<_> = texts
+        .iter()
+        .filter_map(|(name, text)| Some(format!("{}={}", name, text.as_deref()?)))
+        .collect();
+
+    let mut block = Vec::new();
+    block.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    block.extend_from_slice(VENDOR);
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        block.extend_from_slice(comment.as_bytes());
+    }
+    block
+}
@@ -1,14 +1,65 @@
-struct RNG(u64);
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+/// SplitMix64, which gives unrelated outputs for close inputs
+fn scramble(x: u64) -> u64 {
+    let mut x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Maps a random integer uniformly to [-1, 1]
+fn to_unit(x: u64) -> f32 {
+    x as f32 / u64::MAX as f32 * 2.0 - 1.0
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let x = scramble(seed);
 
-impl RNG {
-    pub fn next(&mut self) -> u64 {
-        let RNG(mut x) = self;
+        // Xorshift gets stuck at zero
+        Rng(if x == 0 { 12345 } else { x })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let Rng(mut x) = self;
         x ^= x << 13;
         x ^= x >> 7;
         x ^= x << 17;
-        *self = RNG(x);
+        *self = Rng(x);
         x
     }
+
+    /// Uniformly distributed in [-1, 1]
+    pub fn next_f32(&mut self) -> f32 {
+        to_unit(self.next_u64())
+    }
+}
+
+/// 15-bit linear-feedback shift register of the NES noise channel
+#[derive(Debug, Clone)]
+pub struct Lfsr {
+    register: u16,
+    short: bool,
+}
+
+impl Lfsr {
+    /// The short mode loops every 93 steps and sounds metallic
+    pub fn new(short: bool) -> Self {
+        Lfsr { register: 1, short }
+    }
+
+    pub fn step(&mut self) -> f32 {
+        let tap = if self.short { 6 } else { 1 };
+        let feedback = (self.register ^ (self.register >> tap)) & 1;
+        self.register = (self.register >> 1) | (feedback << 14);
+        if self.register & 1 == 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
 }
 
 use once_cell::sync::OnceCell;
@@ -28,8 +79,7 @@ impl WaveCache {
         }
     }
 
-    fn get_cache(&self, frequency: f32) -> &Mutex<Vec<Option<f32>>> {
-        let idx = (frequency.log2().floor() as usize).min(15);
+    fn get_cache(&self, idx: usize) -> &Mutex<Vec<Option<f32>>> {
         &self.wave.get_or_init(|| {
             (0..16)
                 .map(|_| Mutex::new(vec![None; self.resolution]))
@@ -37,8 +87,11 @@ impl WaveCache {
         })[idx]
     }
 
+    /// Every frequency in an octave shares the samples computed at the top of the octave,
+    /// so that the result does not depend on which frequency was sampled first
     fn sample<F: Fn(f32, f32) -> f32>(&self, frequency: f32, position: f32, func: F) -> f32 {
-        let mut cache = self.get_cache(frequency).lock().unwrap();
+        let octave = (frequency.log2().floor() as usize).min(15);
+        let mut cache = self.get_cache(octave).lock().unwrap();
         let cache_position =
             (position * frequency * self.resolution as f32) as usize % self.resolution;
 
@@ -46,6 +99,8 @@ impl WaveCache {
             return sample;
         }
 
+        let frequency = 2.0f32.powi(octave as i32 + 1);
+        let position = cache_position as f32 / self.resolution as f32 / frequency;
         let sample = func(frequency, position);
         cache[cache_position] = Some(sample);
         sample
@@ -115,9 +170,55 @@ pub fn sine(f: f32, t: f32) -> f32 {
     (2.0 * PI * f * t).sin()
 }
 
-static NOISE_RNG: OnceCell<Mutex<RNG>> = OnceCell::new();
+/// How many times per second white noise changes its value
+const NOISE_CLOCK: f32 = 96000.0;
+
+/// How many times per period of the note pitched noise changes its value
+const PITCHED_NOISE_STEPS: f32 = 16.0;
+
+fn noise_at(seed: u64, step: u64) -> f32 {
+    to_unit(scramble(seed ^ step.wrapping_mul(0xd6e8feb86659fd93)))
+}
+
+/// White noise determined only by the seed and the position, so that renders are reproducible
+pub fn noise(seed: u64, _: f32, position: f32) -> f32 {
+    noise_at(seed, (position * NOISE_CLOCK) as u64)
+}
+
+/// White noise held for a fraction of the period, which sounds higher as the frequency rises
+pub fn pitched_noise(seed: u64, frequency: f32, position: f32) -> f32 {
+    noise_at(seed, (position * frequency * PITCHED_NOISE_STEPS) as u64)
+}
+
+/// One whole loop of the register output
+fn lfsr_table(short: bool) -> &'static [f32] {
+    static LONG: OnceCell<Vec<f32>> = OnceCell::new();
+    static SHORT: OnceCell<Vec<f32>> = OnceCell::new();
+
+    let table = if short { &SHORT } else { &LONG };
+    table.get_or_init(|| {
+        let mut lfsr = Lfsr::new(short);
+        let mut table = vec![lfsr.step()];
+        while lfsr.register != 1 {
+            table.push(lfsr.step());
+        }
+        table
+    })
+}
+
+fn lfsr(short: bool, seed: u64, frequency: f32, position: f32) -> f32 {
+    let table = lfsr_table(short);
+    let len = table.len() as u64;
+    let step = (position * frequency * PITCHED_NOISE_STEPS) as u64;
+    table[((seed % len + step % len) % len) as usize]
+}
+
+/// NES-style noise, clocked in the same way as `pitched_noise`
+pub fn lfsr_long(seed: u64, frequency: f32, position: f32) -> f32 {
+    lfsr(false, seed, frequency, position)
+}
 
-pub fn noise(_: f32, _: f32) -> f32 {
-    let rng = NOISE_RNG.get_or_init(|| Mutex::new(RNG(12345)));
-    rng.lock().unwrap().next() as f32 / std::u64::MAX as f32 * 2.0 - 1.0
+/// NES-style metallic noise which repeats every 93 steps
+pub fn lfsr_short(seed: u64, frequency: f32, position: f32) -> f32 {
+    lfsr(true, seed, frequency, position)
 }
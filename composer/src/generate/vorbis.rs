@@ -0,0 +1,332 @@
+use crate::generate::tags::vorbis_comments;
+use crate::parse::Metadata;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_float, c_int, c_long, c_void};
+use std::ptr;
+
+/// Samples given to libvorbis at once
+const CHUNK_SIZE: usize = 1024;
+const SERIAL: u32 = 0x636f_6d70;
+
+// The structs follow codec.h of libvorbis 1.3 and ogg.h of libogg 1.3 field by field, as
+// libvorbis writes into every one of them
+
+#[repr(C)]
+struct VorbisInfo {
+    version: c_int,
+    channels: c_int,
+    rate: c_long,
+    bitrate_upper: c_long,
+    bitrate_nominal: c_long,
+    bitrate_lower: c_long,
+    bitrate_window: c_long,
+    codec_setup: *mut c_void,
+}
+
+#[repr(C)]
+struct VorbisComment {
+    user_comments: *mut *mut c_char,
+    comment_lengths: *mut c_int,
+    comments: c_int,
+    vendor: *mut c_char,
+}
+
+#[repr(C)]
+struct OggPacket {
+    packet: *mut u8,
+    bytes: c_long,
+    b_o_s: c_long,
+    e_o_s: c_long,
+    granulepos: i64,
+    packetno: i64,
+}
+
+#[repr(C)]
+struct OggPackBuffer {
+    endbyte: c_long,
+    endbit: c_int,
+    buffer: *mut u8,
+    ptr: *mut u8,
+    storage: c_long,
+}
+
+#[repr(C)]
+struct VorbisDspState {
+    analysisp: c_int,
+    vi: *mut VorbisInfo,
+    pcm: *mut *mut c_float,
+    pcmret: *mut *mut c_float,
+    pcm_storage: c_int,
+    pcm_current: c_int,
+    pcm_returned: c_int,
+    preextrapolate: c_int,
+    eofflag: c_int,
+    l_w: c_long,
+    w: c_long,
+    n_w: c_long,
+    center_w: c_long,
+    granulepos: i64,
+    sequence: i64,
+    glue_bits: i64,
+    time_bits: i64,
+    floor_bits: i64,
+    res_bits: i64,
+    backend_state: *mut c_void,
+}
+
+#[repr(C)]
+struct VorbisBlock {
+    pcm: *mut *mut c_float,
+    opb: OggPackBuffer,
+    l_w: c_long,
+    w: c_long,
+    n_w: c_long,
+    pcmend: c_int,
+    mode: c_int,
+    eofflag: c_int,
+    granulepos: i64,
+    sequence: i64,
+    vd: *mut VorbisDspState,
+    localstore: *mut c_void,
+    localtop: c_long,
+    localalloc: c_long,
+    totaluse: c_long,
+    reap: *mut c_void,
+    glue_bits: c_long,
+    time_bits: c_long,
+    floor_bits: c_long,
+    res_bits: c_long,
+    internal: *mut c_void,
+}
+
+// Sizes which the headers give on 64-bit Linux and macOS
+#[cfg(all(target_pointer_width = "64", not(windows)))]
+const _: () = {
+    use std::mem::size_of;
+    assert!(size_of::<VorbisInfo>() == 56);
+    assert!(size_of::<VorbisComment>() == 32);
+    assert!(size_of::<OggPacket>() == 48);
+    assert!(size_of::<OggPackBuffer>() == 40);
+    assert!(size_of::<VorbisDspState>() == 144);
+    assert!(size_of::<VorbisBlock>() == 192);
+};
+
+#[link(name = "vorbisenc")]
+extern "C" {
+    fn vorbis_encode_init_vbr(
+        info: *mut VorbisInfo,
+        channels: c_long,
+        rate: c_long,
+        base_quality: c_float,
+    ) -> c_int;
+}
+
+#[link(name = "vorbis")]
+extern "C" {
+    fn vorbis_info_init(info: *mut VorbisInfo);
+    fn vorbis_info_clear(info: *mut VorbisInfo);
+    fn vorbis_comment_init(comment: *mut VorbisComment);
+    fn vorbis_comment_clear(comment: *mut VorbisComment);
+    fn vorbis_analysis_init(dsp: *mut VorbisDspState, info: *mut VorbisInfo) -> c_int;
+    fn vorbis_dsp_clear(dsp: *mut VorbisDspState);
+    fn vorbis_block_init(dsp: *mut VorbisDspState, block: *mut VorbisBlock) -> c_int;
+    fn vorbis_block_clear(block: *mut VorbisBlock) -> c_int;
+    fn vorbis_analysis_headerout(
+        dsp: *mut VorbisDspState,
+        comment: *mut VorbisComment,
+        header: *mut OggPacket,
+        header_comment: *mut OggPacket,
+        header_code: *mut OggPacket,
+    ) -> c_int;
+    fn vorbis_analysis_buffer(dsp: *mut VorbisDspState, samples: c_int) -> *mut *mut c_float;
+    fn vorbis_analysis_wrote(dsp: *mut VorbisDspState, samples: c_int) -> c_int;
+    fn vorbis_analysis_blockout(dsp: *mut VorbisDspState, block: *mut VorbisBlock) -> c_int;
+    fn vorbis_analysis(block: *mut VorbisBlock, packet: *mut OggPacket) -> c_int;
+    fn vorbis_bitrate_addblock(block: *mut VorbisBlock) -> c_int;
+    fn vorbis_bitrate_flushpacket(dsp: *mut VorbisDspState, packet: *mut OggPacket) -> c_int;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VorbisOptions {
+    /// From -0.1 to 1.0, where 0.4 is about 128 kbps for 44.1 kHz stereo
+    pub quality: f32,
+}
+
+impl Default for VorbisOptions {
+    fn default() -> Self {
+        VorbisOptions { quality: 0.4 }
+    }
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        let message = format!("libvorbis returned {}", ret);
+        Err(io::Error::other(message))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// libvorbis keeps pointers between these, so they stay boxed
+struct State {
+    info: VorbisInfo,
+    comment: VorbisComment,
+    dsp: VorbisDspState,
+    block: VorbisBlock,
+}
+
+/// Writes a mono Ogg Vorbis file with the variable bitrate mode of libvorbis
+pub struct VorbisWriter<W: Write> {
+    packets: Option<PacketWriter<W>>,
+    state: Box<State>,
+    /// Whether `dsp` and `block` have to be cleared
+    analyzing: bool,
+    chunk: Vec<f32>,
+}
+
+impl<W: Write> VorbisWriter<W> {
+    pub fn new(
+        writer: W,
+        sample_rate: u32,
+        options: VorbisOptions,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let mut vorbis = VorbisWriter {
+            packets: Some(PacketWriter::new(writer)),
+            // Every field is a number, a pointer or a byte
+            state: Box::new(unsafe { std::mem::zeroed() }),
+            analyzing: false,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let state = &mut *vorbis.state;
+        let mut header = Vec::new();
+        unsafe {
+            vorbis_info_init(&mut state.info);
+            vorbis_comment_init(&mut state.comment);
+            let rate = sample_rate as c_long;
+            if vorbis_encode_init_vbr(&mut state.info, 1, rate, options.quality) != 0 {
+                let message = "unsupported sample rate or quality for Vorbis";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            check(vorbis_analysis_init(&mut state.dsp, &mut state.info))?;
+            vorbis.analyzing = true;
+            check(vorbis_block_init(&mut state.dsp, &mut state.block))?;
+
+            let mut packets = [ogg_packet(), ogg_packet(), ogg_packet()];
+            let [ident, comment, code] = &mut packets;
+            check(vorbis_analysis_headerout(
+                &mut state.dsp,
+                &mut state.comment,
+                ident,
+                comment,
+                code,
+            ))?;
+            header.extend(packets.iter().map(|packet| packet_bytes(packet)));
+        }
+
+        // The comment header of libvorbis is replaced to write the metadata as UTF-8 as it is
+        let mut comment = b"\x03vorbis".to_vec();
+        comment.extend(vorbis_comments(metadata));
+        comment.push(1); // Framing
+        header[1] = comment.into_boxed_slice();
+
+        // The identification header is on its own page, and the audio begins on a new page
+        let mut header = header.into_iter();
+        let packets = vorbis.packets.as_mut().unwrap();
+        let end_page = PacketWriteEndInfo::EndPage;
+        let normal = PacketWriteEndInfo::NormalPacket;
+        packets.write_packet(header.next().unwrap(), SERIAL, end_page, 0)?;
+        packets.write_packet(header.next().unwrap(), SERIAL, normal, 0)?;
+        packets.write_packet(header.next().unwrap(), SERIAL, end_page, 0)?;
+        Ok(vorbis)
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.chunk.push(sample);
+        if self.chunk.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let length = self.chunk.len() as c_int;
+        unsafe {
+            let buffer = vorbis_analysis_buffer(&mut self.state.dsp, length);
+            ptr::copy_nonoverlapping(self.chunk.as_ptr(), *buffer, self.chunk.len());
+            check(vorbis_analysis_wrote(&mut self.state.dsp, length))?;
+        }
+        self.chunk.clear();
+        self.write_packets()
+    }
+
+    fn write_packets(&mut self) -> io::Result<()> {
+        let state = &mut *self.state;
+        let packets = self.packets.as_mut().unwrap();
+        unsafe {
+            while check(vorbis_analysis_blockout(&mut state.dsp, &mut state.block))? == 1 {
+                check(vorbis_analysis(&mut state.block, ptr::null_mut()))?;
+                check(vorbis_bitrate_addblock(&mut state.block))?;
+
+                let mut packet = ogg_packet();
+                while check(vorbis_bitrate_flushpacket(&mut state.dsp, &mut packet))? == 1 {
+                    let info = if packet.e_o_s != 0 {
+                        PacketWriteEndInfo::EndStream
+                    } else {
+                        PacketWriteEndInfo::NormalPacket
+                    };
+                    let granule = packet.granulepos as u64;
+                    packets.write_packet(packet_bytes(&packet), SERIAL, info, granule)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining samples and returns the writer at the end of the file
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.chunk.is_empty() {
+            self.write_chunk()?;
+        }
+        // Writing no samples marks the end of the stream
+        unsafe {
+            check(vorbis_analysis_wrote(&mut self.state.dsp, 0))?;
+        }
+        self.write_packets()?;
+
+        let mut writer = self.packets.take().unwrap().into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Drop for VorbisWriter<W> {
+    fn drop(&mut self) {
+        let state = &mut *self.state;
+        unsafe {
+            if self.analyzing {
+                vorbis_block_clear(&mut state.block);
+                vorbis_dsp_clear(&mut state.dsp);
+            }
+            vorbis_comment_clear(&mut state.comment);
+            vorbis_info_clear(&mut state.info);
+        }
+    }
+}
+
+fn ogg_packet() -> OggPacket {
+    OggPacket {
+        packet: ptr::null_mut(),
+        bytes: 0,
+        b_o_s: 0,
+        e_o_s: 0,
+        granulepos: 0,
+        packetno: 0,
+    }
+}
+
+/// Copies the packet, which libvorbis overwrites later
+unsafe fn packet_bytes(packet: &OggPacket) -> Box<[u8]> {
+    std::slice::from_raw_parts(packet.packet, packet.bytes as usize).into()
+}
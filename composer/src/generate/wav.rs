@@ -0,0 +1,183 @@
+use crate::generate::tags::riff_info;
+use crate::generate::tones::Rng;
+use crate::parse::Metadata;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const DITHER_SEED: u64 = 2463534242;
+
+/// How each sample is stored in a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl SampleFormat {
+    pub fn bits(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Int32 | SampleFormat::Float32 => 32,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            SampleFormat::Float32 => 3,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavOptions {
+    pub format: SampleFormat,
+    /// Adds triangular noise of one step before rounding samples to integers
+    pub dither: bool,
+}
+
+impl Default for WavOptions {
+    fn default() -> Self {
+        WavOptions {
+            format: SampleFormat::Int16,
+            dither: false,
+        }
+    }
+}
+
+/// Converts samples in [-1, 1] to integers of a bit depth
+#[derive(Debug)]
+pub struct Quantizer {
+    scale: f64,
+    rng: Option<Rng>,
+}
+
+impl Quantizer {
+    pub fn new(bits: u16, dither: bool) -> Self {
+        Quantizer {
+            scale: (1u64 << (bits - 1)) as f64 - 1.0,
+            rng: if dither {
+                Some(Rng::new(DITHER_SEED))
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn quantize(&mut self, sample: f32) -> i32 {
+        let noise = match &mut self.rng {
+            // The sum of two uniform noises in [-0.5, 0.5] has a triangular distribution
+            Some(rng) => (rng.next_f32() + rng.next_f32()) as f64 * 0.5,
+            None => 0.0,
+        };
+        let value = (sample as f64 * self.scale + noise).round();
+        value.clamp(-self.scale - 1.0, self.scale) as i32
+    }
+}
+
+/// Writes a mono WAV file sample by sample, filling in the sizes in the header when it is finished
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: SampleFormat,
+    quantizer: Quantizer,
+    /// Where the RIFF chunk begins, as the writer may not be at its start
+    start: u64,
+    fact_at: Option<u64>,
+    data_at: u64,
+    samples: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        options: WavOptions,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let format = options.format;
+        let bits = format.bits();
+        let block_align = bits / 8;
+        let is_float = format == SampleFormat::Float32;
+        let start = writer.stream_position()?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes()); // Chunk Size: Overwritten when finished
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        // Formats other than integer PCM have the size of the extension, which is empty
+        header.extend_from_slice(&(if is_float { 18u32 } else { 16 }).to_le_bytes());
+        header.extend_from_slice(&format.format_tag().to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // Channels
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits.to_le_bytes());
+        let fact_at = if is_float {
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes()); // Samples: Overwritten when finished
+            Some(start + header.len() as u64 - 4)
+        } else {
+            None
+        };
+        header.extend(riff_info(metadata));
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes()); // Data Size: Overwritten when finished
+        let data_at = start + header.len() as u64;
+        writer.write_all(&header)?;
+
+        Ok(WavWriter {
+            writer,
+            format,
+            quantizer: Quantizer::new(bits, options.dither),
+            start,
+            fact_at,
+            data_at,
+            samples: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        let bytes = match self.format {
+            SampleFormat::Float32 => sample.to_le_bytes(),
+            _ => self.quantizer.quantize(sample).to_le_bytes(),
+        };
+        let width = self.format.bits() as usize / 8;
+        self.writer.write_all(&bytes[..width])?;
+        self.samples += 1;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and returns the writer, positioned at the end of the file
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * (self.format.bits() / 8) as u64;
+        let pad = data_size % 2;
+        if pad != 0 {
+            self.writer.write_all(&[0])?;
+        }
+        let end = self.data_at + data_size + pad;
+        let riff_size = end - self.start - 8;
+        if riff_size > u32::MAX as u64 {
+            let message = "WAV files cannot exceed 4 GiB";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        self.writer.seek(SeekFrom::Start(self.start + 4))?;
+        self.writer.write_all(&(riff_size as u32).to_le_bytes())?;
+        if let Some(fact_at) = self.fact_at {
+            self.writer.seek(SeekFrom::Start(fact_at))?;
+            let samples = self.samples as u32;
+            self.writer.write_all(&samples.to_le_bytes())?;
+        }
+        self.writer.seek(SeekFrom::Start(self.data_at - 4))?;
+        self.writer.write_all(&(data_size as u32).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -1,3 +1,6 @@
+pub mod format;
 pub mod generate;
 pub mod parse;
+pub mod playback;
+pub mod ratio;
 pub mod tokenize;
@@ -0,0 +1,66 @@
+use crate::parse::{
+    Arpeggio, ArpeggioDirection, Instruction, ParseError, ParseResult, RollbackableTokenStream,
+};
+use crate::tokenize::TokenKind;
+
+/// Shortest note of an arpeggio, as the number of notes in a whole note
+pub const MAX_SPEED: usize = 256;
+/// Octaves a chord can be spread over
+pub const MAX_OCTAVES: usize = 8;
+
+pub fn arpeggio(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('a').is_err() {
+        return Ok(None);
+    }
+
+    let params_at = stream.cursor();
+    let (speed_at, speed) = stream.take_number()?;
+    if speed == 0 {
+        return Ok(Some(Instruction::Arpeggio(None)));
+    }
+    if speed > MAX_SPEED {
+        return Err(ParseError::UnexpectedToken((
+            speed_at,
+            TokenKind::Number(speed),
+        )));
+    }
+
+    let mut direction = ArpeggioDirection::Up;
+    let mut octaves = 1;
+
+    if stream.expect_character(',').is_ok() {
+        direction = match stream.take_number()? {
+            (_, 0) => ArpeggioDirection::Up,
+            (_, 1) => ArpeggioDirection::Down,
+            (_, 2) => ArpeggioDirection::UpDown,
+            (token_at, x) => {
+                return Err(ParseError::UnexpectedToken((
+                    token_at,
+                    TokenKind::Number(x),
+                )))
+            }
+        };
+
+        if stream.expect_character(',').is_ok() {
+            let (octaves_at, x) = stream.take_number()?;
+            if x > MAX_OCTAVES {
+                return Err(ParseError::UnexpectedToken((
+                    octaves_at,
+                    TokenKind::Number(x),
+                )));
+            }
+            octaves = x.max(1);
+
+            if stream.expect_character(',').is_ok() {
+                let provided = 3 + stream.comma_separated_numbers().len();
+                return Err(ParseError::WrongParamsNumber(params_at, 3, provided));
+            }
+        }
+    }
+
+    Ok(Some(Instruction::Arpeggio(Some(Arpeggio {
+        speed,
+        direction,
+        octaves,
+    }))))
+}
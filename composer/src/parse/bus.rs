@@ -0,0 +1,38 @@
+use crate::parse::tone::tone;
+use crate::parse::{Instruction, ParseError, ParseResult, RollbackableTokenStream, ToneModifier};
+
+pub fn bus(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('b').is_err() {
+        return Ok(None);
+    }
+
+    let (_, name) = stream.take_brace_string()?;
+    stream.expect_character('(')?;
+
+    let mut effects = Vec::new();
+    while stream.expect_character(')').is_err() {
+        let token = stream.peek().cloned();
+        match tone(stream)? {
+            Some(Instruction::ToneModifier(ToneModifier::Effect(effect))) => effects.push(effect),
+            _ => match token {
+                Some(token) => return Err(ParseError::UnexpectedToken(token)),
+                None => return Err(ParseError::UnexpectedEOF),
+            },
+        }
+    }
+
+    Ok(Some(Instruction::Bus(name.to_string(), effects)))
+}
+
+pub fn send(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('x').is_err() {
+        return Ok(None);
+    }
+
+    let (_, name) = stream.take_brace_string()?;
+    let (_, level) = stream.take_number()?;
+    Ok(Some(Instruction::Send(
+        name.to_string(),
+        level as f32 / 100.0,
+    )))
+}
@@ -0,0 +1,17 @@
+use crate::parse::{Instruction, ParseError, ParseResult, RollbackableTokenStream};
+use crate::tokenize::TokenKind;
+
+pub fn drum_kit(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('k').is_err() {
+        return Ok(None);
+    }
+
+    match stream.take_number()? {
+        (_, 0) => Ok(Some(Instruction::DrumKit(false))),
+        (_, 1) => Ok(Some(Instruction::DrumKit(true))),
+        (token_at, x) => Err(ParseError::UnexpectedToken((
+            token_at,
+            TokenKind::Number(x),
+        ))),
+    }
+}
@@ -0,0 +1,41 @@
+use crate::parse::{Instruction, ParseError, ParseResult, RollbackableTokenStream, Swing};
+use crate::tokenize::TokenKind;
+
+pub fn swing(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('s').is_err() {
+        return Ok(None);
+    }
+
+    let (_, division) = stream.take_number()?;
+    if division == 0 {
+        return Ok(Some(Instruction::Swing(None)));
+    }
+
+    stream.expect_character(',')?;
+    let (ratio_at, ratio) = stream.take_number()?;
+    if ratio == 0 || ratio >= 1000 {
+        return Err(ParseError::UnexpectedToken((
+            ratio_at,
+            TokenKind::Number(ratio),
+        )));
+    }
+
+    Ok(Some(Instruction::Swing(Some(Swing { division, ratio }))))
+}
+
+pub fn humanize(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('j').is_err() {
+        return Ok(None);
+    }
+
+    let params_at = stream.cursor();
+    let params = stream.comma_separated_numbers();
+    if params.len() < 2 || params.len() > 3 {
+        return Err(ParseError::WrongParamsNumber(params_at, 2, params.len()));
+    }
+
+    let timing = params[0] as f32 / 1000.0;
+    let velocity = params[1] as f32 / 100.0;
+    let seed = params.get(2).map(|&x| x as u64);
+    Ok(Some(Instruction::Humanize(timing, velocity, seed)))
+}
@@ -0,0 +1,10 @@
+use crate::parse::{Instruction, ParseResult, RollbackableTokenStream};
+
+pub fn marker(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('m').is_err() {
+        return Ok(None);
+    }
+
+    let (_, name) = stream.take_brace_string()?;
+    Ok(Some(Instruction::Marker(name.to_string())))
+}
@@ -0,0 +1,41 @@
+use crate::parse::{Instruction, MetadataField, ParseError, ParseResult, RollbackableTokenStream};
+use crate::tokenize::TokenKind;
+
+fn take_quoted_string(stream: &mut RollbackableTokenStream) -> Result<String, ParseError> {
+    match stream.peek() {
+        Some((_, TokenKind::QuotedString(string))) => {
+            stream.next();
+            Ok(string.clone())
+        }
+        Some(x) => Err(ParseError::UnexpectedToken(x.clone())),
+        None => Err(ParseError::UnexpectedEOF),
+    }
+}
+
+pub fn metadata(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('#').is_err() {
+        return Ok(None);
+    }
+
+    let key_token = stream.peek().cloned();
+    let mut key = String::new();
+    while let Some(&(_, TokenKind::Character(ch))) = stream.peek() {
+        if !ch.is_ascii_alphabetic() {
+            break;
+        }
+        key.push(ch);
+        stream.next();
+    }
+
+    let field = match key.as_str() {
+        "title" => MetadataField::Title(take_quoted_string(stream)?),
+        "composer" => MetadataField::Composer(take_quoted_string(stream)?),
+        "tempo" => MetadataField::Tempo(stream.take_number()?.1),
+        "track" => MetadataField::TrackName(take_quoted_string(stream)?),
+        _ => match key_token {
+            Some(token) => return Err(ParseError::UnexpectedToken(token)),
+            None => return Err(ParseError::UnexpectedEOF),
+        },
+    };
+    Ok(Some(Instruction::Metadata(field)))
+}
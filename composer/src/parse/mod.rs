@@ -1,8 +1,16 @@
+pub mod arpeggio;
+pub mod bus;
+pub mod drum;
+pub mod groove;
+pub mod marker;
+pub mod metadata;
 pub mod note;
 pub mod octave;
 pub mod repeat;
+pub mod seed;
 pub mod tempo;
 pub mod tone;
+pub mod tuplet;
 pub mod volume;
 
 use crate::tokenize::{Token, TokenKind};
@@ -25,6 +33,33 @@ pub enum NoteLength {
     DefaultLength,
     Dot,
     Length(usize),
+    Ticks(usize),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RampCurve {
+    Linear,
+    Exponential,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ArpeggioDirection {
+    Up,
+    Down,
+    UpDown,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Arpeggio {
+    pub speed: usize,
+    pub direction: ArpeggioDirection,
+    pub octaves: usize,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Swing {
+    pub division: usize,
+    pub ratio: usize,
 }
 
 #[derive(PartialEq, Debug)]
@@ -37,40 +72,118 @@ pub enum ToneModifier {
     Gate(f32),
     Tune(f32),
     Effect(Effect),
+    ClearEffects,
     Volume(f32),
 }
 
+#[derive(PartialEq, Debug, Clone)]
+pub enum MetadataField {
+    Title(String),
+    Composer(String),
+    /// Tempo at the beginning of the first track, which later tracks carry on like `T`
+    Tempo(usize),
+    /// Label of the track in which it is written
+    TrackName(String),
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Instruction {
     Note(isize, Vec<NoteLength>),
     PlayPCM(usize, f32),
     Chord(Vec<isize>, Vec<NoteLength>),
+    Arpeggio(Option<Arpeggio>),
+    Swing(Option<Swing>),
+    DrumKit(bool),
+    Seed(u64),
+    Humanize(f32, f32, Option<u64>),
     Rest(Vec<NoteLength>),
     Octave(isize),
     Tempo(usize),
+    TempoRamp(usize, Vec<NoteLength>, RampCurve),
     Volume(f32),
+    VolumeRamp(f32, Vec<NoteLength>, RampCurve),
     ToneModifier(ToneModifier),
     Repeat(Track, usize),
+    Tuplet(Track, Vec<NoteLength>),
     Length(Vec<NoteLength>),
     Synthesize(Vec<Vec<ToneModifier>>),
+    /// Effects of a bus shared by all tracks
+    Bus(String, Vec<Effect>),
+    /// Level of the track sent to a bus
+    Send(String, f32),
+    /// Named position which playback can start from
+    Marker(String),
+    Metadata(MetadataField),
 }
 
 pub type Track = Vec<Instruction>;
-pub type ParsedMML = Vec<Track>;
+
+/// Information about the song given in the header
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub composer: Option<String>,
+    pub tempo: Option<usize>,
+}
+
+#[derive(PartialEq, Debug, Default)]
+pub struct ParsedMML {
+    pub metadata: Metadata,
+    pub tracks: Vec<Track>,
+    /// Label of each track, if it is given
+    pub track_names: Vec<Option<String>>,
+}
+
+impl ParsedMML {
+    /// Index of the first track with the name
+    pub fn track_index(&self, name: &str) -> Option<usize> {
+        self.track_names
+            .iter()
+            .position(|x| x.as_deref() == Some(name))
+    }
+
+    /// Label of the track, or its number starting from 0 if it has no name
+    pub fn track_name(&self, track: usize) -> String {
+        match self.track_names.get(track) {
+            Some(Some(name)) => name.clone(),
+            _ => track.to_string(),
+        }
+    }
+}
 
 type ParseResult = Result<Option<Instruction>, ParseError>;
 
+/// Deepest nesting of brackets allowed, so that the recursive parsing cannot overflow the stack
+pub const MAX_NESTING: usize = 64;
+
+/// Most instructions a song may have once its repeats are expanded, so that a few characters
+/// cannot take unbounded time and memory to generate
+pub const MAX_EXPANDED: usize = 1_000_000;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ParseError {
     UnexpectedToken(Token),
     WrongParamsNumber(usize, usize, usize), // Params at, expected, provided
     UnexpectedEOF,
+    TooDeeplyNested(usize),
+    TooLong(usize),
 }
 
 impl ParseError {
     fn unexpected_char(position: usize, ch: char) -> Self {
         Self::UnexpectedToken((position, TokenKind::Character(ch)))
     }
+
+    /// Where the error is in the MML, counted in characters from 1
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            ParseError::UnexpectedToken((token_at, _)) => Some(*token_at),
+            ParseError::WrongParamsNumber(params_at, _, _) => Some(*params_at),
+            ParseError::UnexpectedEOF => None,
+            ParseError::TooDeeplyNested(token_at) => Some(*token_at),
+            ParseError::TooLong(token_at) => Some(*token_at),
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -85,6 +198,16 @@ impl fmt::Display for ParseError {
                 provided, params_at, expected
             ),
             ParseError::UnexpectedEOF => write!(f, "Unexpected EOF"),
+            ParseError::TooDeeplyNested(token_at) => write!(
+                f,
+                "Brackets at {} are nested deeper than {} levels",
+                token_at, MAX_NESTING
+            ),
+            ParseError::TooLong(token_at) => write!(
+                f,
+                "The song is longer than {} instructions at {}",
+                MAX_EXPANDED, token_at
+            ),
         }
     }
 }
@@ -93,6 +216,7 @@ impl fmt::Display for ParseError {
 pub struct RollbackableTokenStream<'a> {
     tokens: &'a [Token],
     cursor: usize,
+    nesting: usize,
 }
 
 impl<'a> Iterator for RollbackableTokenStream<'a> {
@@ -175,7 +299,11 @@ impl<'a> RollbackableTokenStream<'a> {
         if numbers.len() == expected {
             Ok(numbers)
         } else {
-            Err(ParseError::WrongParamsNumber(params_at, expected, numbers.len()))
+            Err(ParseError::WrongParamsNumber(
+                params_at,
+                expected,
+                numbers.len(),
+            ))
         }
     }
 
@@ -194,19 +322,70 @@ impl<'a> RollbackableTokenStream<'a> {
         self.cursor
     }
 
+    /// Enters brackets opened at `token_at`
+    pub fn enter(&mut self, token_at: usize) -> Result<(), ParseError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(ParseError::TooDeeplyNested(token_at));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.nesting = self.nesting.saturating_sub(1);
+    }
+
+    /// A stream of other tokens at the same nesting level
+    pub fn with_tokens<'b>(&self, tokens: &'b [Token]) -> RollbackableTokenStream<'b> {
+        RollbackableTokenStream {
+            tokens,
+            cursor: 0,
+            nesting: self.nesting,
+        }
+    }
+
     pub fn new(tokens: &'a [Token]) -> Self {
-        RollbackableTokenStream { tokens, cursor: 0 }
+        RollbackableTokenStream {
+            tokens,
+            cursor: 0,
+            nesting: 0,
+        }
+    }
+}
+
+pub fn ramp_curve(stream: &mut RollbackableTokenStream) -> Option<RampCurve> {
+    if stream.expect_character('~').is_ok() {
+        Some(RampCurve::Linear)
+    } else if stream.expect_character('^').is_ok() {
+        Some(RampCurve::Exponential)
+    } else {
+        None
     }
 }
 
 pub type Parser = fn(&mut RollbackableTokenStream) -> ParseResult;
 
+/// Number of instructions after the repeats are expanded
+fn expanded_len(instruction: &Instruction) -> usize {
+    let track_len = |track: &Track| {
+        track
+            .iter()
+            .fold(0, |len: usize, x| len.saturating_add(expanded_len(x)))
+    };
+    match instruction {
+        Instruction::Repeat(track, times) => track_len(track).saturating_mul(*times),
+        Instruction::Tuplet(track, _) => track_len(track),
+        _ => 1,
+    }
+}
+
 pub fn parse_stream(
     stream: &mut RollbackableTokenStream,
     inside_bracket: bool,
-) -> Result<ParsedMML, ParseError> {
+) -> Result<Vec<Track>, ParseError> {
     let mut parsed = Vec::new();
     let mut track = Vec::new();
+    let mut expanded: usize = 0;
 
     'main_loop: while !stream.empty() {
         if let Some(&(token_at, TokenKind::Character(';'))) = stream.peek() {
@@ -238,15 +417,35 @@ pub fn parse_stream(
             note::length,
             octave::octave,
             tempo::tempo,
+            arpeggio::arpeggio,
+            groove::swing,
+            groove::humanize,
+            drum::drum_kit,
+            seed::seed,
+            bus::bus,
+            bus::send,
+            marker::marker,
             tone::synthesize,
             tone::tone,
             volume::volume,
             repeat::repeat,
+            tuplet::tuplet,
+            metadata::metadata,
         ];
 
         for &parser in &parsers {
             stream.rollback();
+            let token_at = stream.peek().map_or(0, |&(token_at, _)| token_at);
             if let Some(x) = parser(stream)? {
+                expanded = expanded.saturating_add(expanded_len(&x));
+                if expanded > MAX_EXPANDED {
+                    return Err(ParseError::TooLong(token_at));
+                }
+                if let (true, Instruction::Metadata(_)) = (inside_bracket, &x) {
+                    stream.rollback();
+                    let token = stream.next().unwrap();
+                    return Err(ParseError::UnexpectedToken(token.clone()));
+                }
                 track.push(x);
                 stream.accept();
                 continue 'main_loop;
@@ -270,5 +469,32 @@ pub fn parse_stream(
 
 pub fn parse(tokens: &[Token]) -> Result<ParsedMML, ParseError> {
     let mut stream = RollbackableTokenStream::new(tokens);
-    parse_stream(&mut stream, false)
+    let mut parsed = ParsedMML::default();
+
+    let mut name = None;
+    for mut track in parse_stream(&mut stream, false)? {
+        let is_empty = track.is_empty();
+        track.retain(|inst| match inst {
+            Instruction::Metadata(field) => {
+                let metadata = &mut parsed.metadata;
+                match field {
+                    MetadataField::Title(title) => metadata.title = Some(title.clone()),
+                    MetadataField::Composer(composer) => metadata.composer = Some(composer.clone()),
+                    MetadataField::Tempo(tempo) => metadata.tempo = Some(*tempo),
+                    MetadataField::TrackName(track_name) => name = Some(track_name.clone()),
+                }
+                false
+            }
+            _ => true,
+        });
+        // A section of only metadata, such as a header ended by `;`, is not a track, and the
+        // name given in it goes to the next track
+        if track.is_empty() && !is_empty {
+            continue;
+        }
+        parsed.tracks.push(track);
+        parsed.track_names.push(name.take());
+    }
+
+    Ok(parsed)
 }
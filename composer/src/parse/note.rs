@@ -5,9 +5,15 @@ use crate::try_or_ok_none;
 pub fn parse_length(stream: &mut RollbackableTokenStream) -> Vec<NoteLength> {
     let mut length = vec![];
     loop {
+        let mut lookahead = stream.clone();
         if let Some(&(_, TokenKind::Number(num))) = stream.peek() {
             stream.next();
             length.push(NoteLength::Length(num))
+        } else if let (Some((_, TokenKind::Character('%'))), Some(&(_, TokenKind::Number(num)))) =
+            (lookahead.next(), lookahead.next())
+        {
+            *stream = lookahead;
+            length.push(NoteLength::Ticks(num))
         } else {
             length.push(NoteLength::DefaultLength);
         }
@@ -1,14 +1,17 @@
 use crate::parse::{parse_stream, Instruction, ParseResult, RollbackableTokenStream};
 
 pub fn repeat(stream: &mut RollbackableTokenStream) -> ParseResult {
-    if stream.expect_character('[').is_err() {
-        return Ok(None);
-    }
+    let bracket_at = match stream.peek() {
+        Some(&(token_at, _)) if stream.expect_character('[').is_ok() => token_at,
+        _ => return Ok(None),
+    };
 
     let mut cloned_stream = stream.clone();
     cloned_stream.accept();
 
+    cloned_stream.enter(bracket_at)?;
     let inside = parse_stream(&mut cloned_stream, true)?.remove(0); // Take the first track
+    cloned_stream.leave();
 
     let (_, num) = cloned_stream.take_number()?;
     *stream = cloned_stream;
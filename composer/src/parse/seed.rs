@@ -0,0 +1,10 @@
+use crate::parse::{Instruction, ParseResult, RollbackableTokenStream};
+
+pub fn seed(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if stream.expect_character('@').is_err() || stream.expect_character('r').is_err() {
+        return Ok(None);
+    }
+
+    let (_, seed) = stream.take_number()?;
+    Ok(Some(Instruction::Seed(seed as u64)))
+}
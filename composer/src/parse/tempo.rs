@@ -1,10 +1,18 @@
-use crate::parse::{Instruction, ParseResult, RollbackableTokenStream};
+use crate::parse::note::parse_length;
+use crate::parse::{ramp_curve, Instruction, ParseResult, RollbackableTokenStream};
 
 pub fn tempo(stream: &mut RollbackableTokenStream) -> ParseResult {
     if stream.expect_character('t').is_err() {
         return Ok(None);
     }
 
+    if let Some(curve) = ramp_curve(stream) {
+        let (_, tempo) = stream.take_number()?;
+        stream.expect_character(',')?;
+        let length = parse_length(stream);
+        return Ok(Some(Instruction::TempoRamp(tempo, length, curve)));
+    }
+
     let (_, tempo) = stream.take_number()?;
     Ok(Some(Instruction::Tempo(tempo)))
 }
@@ -1,4 +1,6 @@
 use crate::parse::{Instruction, ParseError, ParseResult, RollbackableTokenStream, ToneModifier};
+use crate::tokenize::TokenKind;
+use std::mem::discriminant;
 
 fn hex_to_num(hex: u8) -> Option<usize> {
     if b'0' <= hex && hex <= b'9' {
@@ -12,14 +14,133 @@ fn hex_to_num(hex: u8) -> Option<usize> {
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// Q of `@FL` and `@FH` when it is omitted, which is what the filters had before Q was configurable
+pub const DEFAULT_FILTER_Q: f32 = std::f32::consts::SQRT_2;
+
+/// Filter types of the Audio EQ Cookbook, in the order of the numbers of `@FQ`
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    AllPass,
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Effect {
-    Delay { delay: f32, feedback: f32 },
-    LowPassFilter { cut_off: f32 },
-    HighPassFilter { cut_off: f32 },
+    Delay {
+        delay: f32,
+        feedback: f32,
+    },
+    Filter {
+        kind: FilterKind,
+        cut_off: f32,
+        q: f32,
+        gain: f32,
+    },
+    FilterEnvelope {
+        from: f32,
+        to: f32,
+        time: f32,
+        q: f32,
+    },
+    Reverb {
+        room_size: f32,
+        damping: f32,
+        mix: f32,
+    },
+    Chorus {
+        rate: f32,
+        depth: f32,
+        mix: f32,
+    },
+    Flanger {
+        rate: f32,
+        depth: f32,
+        feedback: f32,
+        mix: f32,
+    },
+    Phaser {
+        rate: f32,
+        depth: f32,
+        mix: f32,
+    },
+    Distortion {
+        drive: f32,
+        mix: f32,
+    },
+    BitCrusher {
+        bits: usize,
+    },
+    RateReducer {
+        rate: f32,
+    },
+    Compressor {
+        threshold: f32,
+        ratio: f32,
+        attack: f32,
+        release: f32,
+    },
+}
+
+impl Effect {
+    /// Effects of the same kind replace each other in a chain. Filters of different types are different kinds.
+    pub fn same_kind(&self, other: &Effect) -> bool {
+        match (self, other) {
+            (Effect::Filter { kind: a, .. }, Effect::Filter { kind: b, .. }) => a == b,
+            _ => discriminant(self) == discriminant(other),
+        }
+    }
+}
+
+/// Takes between `min` and `max` parameters
+fn optional_params(
+    stream: &mut RollbackableTokenStream,
+    min: usize,
+    max: usize,
+) -> Result<Vec<usize>, ParseError> {
+    let params_at = stream.cursor();
+    let params = stream.comma_separated_numbers();
+    if params.len() < min {
+        Err(ParseError::WrongParamsNumber(params_at, min, params.len()))
+    } else if max < params.len() {
+        Err(ParseError::WrongParamsNumber(params_at, max, params.len()))
+    } else {
+        Ok(params)
+    }
+}
+
+fn filter(kind: FilterKind, params: &[usize]) -> ParseResult {
+    let cut_off = params[0] as f32;
+    let q = params
+        .get(1)
+        .map_or(DEFAULT_FILTER_Q, |&q| q as f32 / 1000.0);
+    let gain = params.get(2).map_or(1.0, |&gain| gain as f32 / 1000.0);
+    Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+        Effect::Filter {
+            kind,
+            cut_off,
+            q,
+            gain,
+        },
+    ))))
 }
 
 fn effects(stream: &mut RollbackableTokenStream) -> ParseResult {
+    if let Ok((number_at, number)) = stream.take_number() {
+        return match number {
+            0 => Ok(Some(Instruction::ToneModifier(ToneModifier::ClearEffects))),
+            _ => Err(ParseError::UnexpectedToken((
+                number_at,
+                TokenKind::Number(number),
+            ))),
+        };
+    }
+
     let (effect_at, effect) = stream.take_character()?;
 
     match effect {
@@ -36,20 +157,144 @@ fn effects(stream: &mut RollbackableTokenStream) -> ParseResult {
                 Effect::Delay { delay, feedback },
             ))))
         }
-        'l' => {
+        'l' => filter(FilterKind::LowPass, &optional_params(stream, 1, 2)?),
+        'h' => filter(FilterKind::HighPass, &optional_params(stream, 1, 2)?),
+        'q' => {
+            let (kind_at, kind) = stream.take_number()?;
+            let kind = match kind {
+                0 => FilterKind::LowPass,
+                1 => FilterKind::HighPass,
+                2 => FilterKind::BandPass,
+                3 => FilterKind::Notch,
+                4 => FilterKind::AllPass,
+                5 => FilterKind::Peak,
+                6 => FilterKind::LowShelf,
+                7 => FilterKind::HighShelf,
+                _ => {
+                    return Err(ParseError::UnexpectedToken((
+                        kind_at,
+                        TokenKind::Number(kind),
+                    )))
+                }
+            };
+            stream.expect_character(',')?;
+            filter(kind, &optional_params(stream, 1, 3)?)
+        }
+        'e' => {
+            let params = stream.comma_separated_n_numbers(4)?;
+
+            let from = params[0] as f32;
+            let to = params[1] as f32;
+            let time = params[2] as f32 / 1000.0;
+            let q = params[3] as f32 / 1000.0;
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                Effect::FilterEnvelope { from, to, time, q },
+            ))))
+        }
+        'r' => {
+            let params: Vec<_> = stream
+                .comma_separated_n_numbers(3)?
+                .into_iter()
+                .map(|x| x as f32 / 1000.0)
+                .collect();
+
+            let room_size = params[0];
+            let damping = params[1];
+            let mix = params[2];
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                Effect::Reverb {
+                    room_size,
+                    damping,
+                    mix,
+                },
+            ))))
+        }
+        'c' | 'p' => {
+            let params: Vec<_> = stream
+                .comma_separated_n_numbers(3)?
+                .into_iter()
+                .map(|x| x as f32 / 1000.0)
+                .collect();
+
+            let rate = params[0];
+            let depth = params[1];
+            let mix = params[2];
+            let effect = if effect == 'c' {
+                Effect::Chorus { rate, depth, mix }
+            } else {
+                Effect::Phaser { rate, depth, mix }
+            };
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                effect,
+            ))))
+        }
+        'f' => {
+            let params: Vec<_> = stream
+                .comma_separated_n_numbers(4)?
+                .into_iter()
+                .map(|x| x as f32 / 1000.0)
+                .collect();
+
+            let rate = params[0];
+            let depth = params[1];
+            let feedback = params[2];
+            let mix = params[3];
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                Effect::Flanger {
+                    rate,
+                    depth,
+                    feedback,
+                    mix,
+                },
+            ))))
+        }
+        'o' => {
+            let params: Vec<_> = stream
+                .comma_separated_n_numbers(2)?
+                .into_iter()
+                .map(|x| x as f32 / 1000.0)
+                .collect();
+
+            let drive = params[0];
+            let mix = params[1];
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                Effect::Distortion { drive, mix },
+            ))))
+        }
+        'b' => {
             let params = stream.comma_separated_n_numbers(1)?;
 
-            let cut_off = params[0] as f32;
+            let bits = params[0];
             Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
-                Effect::LowPassFilter { cut_off },
+                Effect::BitCrusher { bits },
             ))))
         }
-        'h' => {
+        's' => {
             let params = stream.comma_separated_n_numbers(1)?;
 
-            let cut_off = params[0] as f32;
+            let rate = params[0] as f32;
+            Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
+                Effect::RateReducer { rate },
+            ))))
+        }
+        'm' => {
+            let params: Vec<_> = stream
+                .comma_separated_n_numbers(4)?
+                .into_iter()
+                .map(|x| x as f32 / 1000.0)
+                .collect();
+
+            let threshold = params[0];
+            let ratio = params[1];
+            let attack = params[2];
+            let release = params[3];
             Ok(Some(Instruction::ToneModifier(ToneModifier::Effect(
-                Effect::HighPassFilter { cut_off },
+                Effect::Compressor {
+                    threshold,
+                    ratio,
+                    attack,
+                    release,
+                },
             ))))
         }
         _ => Err(ParseError::unexpected_char(effect_at, effect)),
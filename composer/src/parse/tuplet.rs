@@ -0,0 +1,30 @@
+use crate::parse::note::parse_length;
+use crate::parse::{parse_stream, Instruction, ParseError, ParseResult, RollbackableTokenStream};
+use crate::tokenize::{tokenize, TokenKind};
+
+pub fn tuplet(stream: &mut RollbackableTokenStream) -> ParseResult {
+    let (token_at, string) = if let Ok(brace_string) = stream.take_brace_string() {
+        brace_string
+    } else {
+        return Ok(None);
+    };
+
+    let error =
+        || ParseError::UnexpectedToken((token_at, TokenKind::BraceString(string.to_string())));
+    let mut tokens: Vec<_> = tokenize(string)
+        .map_err(|_| error())?
+        .into_iter()
+        .map(|(position, token)| (token_at + position, token))
+        .collect();
+    tokens.push((token_at + string.len() + 1, TokenKind::Character(']')));
+
+    let mut inner_stream = stream.with_tokens(&tokens);
+    inner_stream.enter(token_at)?;
+    let inside = parse_stream(&mut inner_stream, true)?.remove(0);
+    if let Some(token) = inner_stream.next() {
+        return Err(ParseError::UnexpectedToken(token.clone()));
+    }
+
+    let length = parse_length(stream);
+    Ok(Some(Instruction::Tuplet(inside, length)))
+}
@@ -1,10 +1,22 @@
-use crate::parse::{Instruction, ParseResult, RollbackableTokenStream};
+use crate::parse::note::parse_length;
+use crate::parse::{ramp_curve, Instruction, ParseResult, RollbackableTokenStream};
 
 pub fn volume(stream: &mut RollbackableTokenStream) -> ParseResult {
     if stream.expect_character('v').is_err() {
         return Ok(None);
     }
 
+    if let Some(curve) = ramp_curve(stream) {
+        let (_, volume) = stream.take_number()?;
+        stream.expect_character(',')?;
+        let length = parse_length(stream);
+        return Ok(Some(Instruction::VolumeRamp(
+            volume as f32 / 100.0,
+            length,
+            curve,
+        )));
+    }
+
     let (_, volume) = stream.take_number()?;
     Ok(Some(Instruction::Volume(volume as f32 / 100.0)))
 }
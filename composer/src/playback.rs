@@ -0,0 +1,200 @@
+use crate::generate::Generator;
+use crate::parse::ParsedMML;
+use std::sync::{Mutex, MutexGuard};
+
+/// Part of the song to play, in samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Region {
+    pub start: usize,
+    pub end: Option<usize>,
+    pub looping: bool,
+}
+
+impl Region {
+    pub fn contains(&self, position: usize) -> bool {
+        self.start <= position && self.end.is_none_or(|end| position < end)
+    }
+}
+
+/// Generator of the song with the tracks muted and soloed
+pub fn generator(
+    sample_rate: u32,
+    mml: &ParsedMML,
+    muted: &[usize],
+    soloed: &[usize],
+) -> Generator {
+    let mut generator = Generator::new(sample_rate as f32, mml);
+    for &track in muted {
+        generator.set_muted(track, true);
+    }
+    for &track in soloed {
+        generator.set_soloed(track, true);
+    }
+    generator
+}
+
+/// A parsed song with the tracks and the region to play
+#[derive(Debug)]
+pub struct Song {
+    pub mml: ParsedMML,
+    pub muted: Vec<usize>,
+    pub soloed: Vec<usize>,
+    pub region: Region,
+}
+
+impl Song {
+    pub fn generator_at(&self, sample_rate: u32, position: usize) -> Generator {
+        let mut generator = generator(sample_rate, &self.mml, &self.muted, &self.soloed);
+        generator.seek(position as f32 / sample_rate as f32);
+        generator
+    }
+}
+
+/// The song being played, shared with the output stream
+#[derive(Debug, Default)]
+pub struct Player {
+    generator: Option<Generator>,
+    /// Generator at the start of the looped region, which takes over when the region ends
+    next: Option<Generator>,
+    /// Samples since the beginning of the song
+    position: usize,
+    region: Region,
+    is_over: bool,
+}
+
+impl Player {
+    pub fn start(&mut self, generator: Generator, position: usize, region: Region) {
+        self.generator = Some(generator);
+        self.next = None;
+        self.position = position;
+        self.region = region;
+        self.is_over = false;
+    }
+
+    /// Silence while there is nothing to play
+    pub fn next_sample(&mut self) -> f32 {
+        let is_in_region = self.region.end.is_none_or(|end| self.position < end);
+        let sample = match &mut self.generator {
+            Some(generator) if is_in_region => generator.next(),
+            _ => None,
+        };
+        if let Some(sample) = sample {
+            self.position += 1;
+            return sample;
+        }
+
+        if !self.region.looping {
+            self.is_over = true;
+            return 0.0;
+        }
+        // Silence is played until the next loop is prepared
+        match self.next.take() {
+            Some(next) => {
+                self.generator = Some(next);
+                self.position = self.region.start;
+                self.next_sample()
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn needs_next(&self) -> bool {
+        self.region.looping && self.generator.is_some() && self.next.is_none()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.is_over
+    }
+}
+
+/// A panic in the stream callback does not leave the player broken
+pub fn lock(player: &Mutex<Player>) -> MutexGuard<'_, Player> {
+    player.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Prepares the next loop without holding the lock, as seeking takes a while
+pub fn prepare_loop(player: &Mutex<Player>, song: &Song, sample_rate: u32) {
+    if lock(player).needs_next() {
+        let next = song.generator_at(sample_rate, song.region.start);
+        lock(player).next = Some(next);
+    }
+}
+
+/// The song played while its file is watched, which is restarted from the mark or where it was
+/// whenever it changes
+#[derive(Debug)]
+pub struct Watcher {
+    sample_rate: u32,
+    song: Option<Song>,
+    mark: Option<usize>,
+    /// Position to start the song from on the next update
+    restart: Option<usize>,
+}
+
+impl Watcher {
+    /// Nothing is played until a song loads if there is no song
+    pub fn new(sample_rate: u32, song: Option<Song>) -> Self {
+        Watcher {
+            sample_rate,
+            restart: song.as_ref().map(|song| song.region.start),
+            song,
+            mark: None,
+        }
+    }
+
+    pub fn song(&self) -> Option<&Song> {
+        self.song.as_ref()
+    }
+
+    pub fn mark(&self) -> Option<usize> {
+        self.mark
+    }
+
+    /// Starts the song if it has to start and prepares its next loop, returning where it started
+    pub fn update(&mut self, player: &Mutex<Player>) -> Option<usize> {
+        let song = self.song.as_ref()?;
+        let started = self.restart.take();
+        if let Some(position) = started {
+            let generator = song.generator_at(self.sample_rate, position);
+            lock(player).start(generator, position, song.region);
+        }
+        prepare_loop(player, song, self.sample_rate);
+        started
+    }
+
+    /// Marks where the player is
+    pub fn set_mark(&mut self, player: &Player) -> usize {
+        self.mark = Some(player.position());
+        player.position()
+    }
+
+    pub fn remove_mark(&mut self) {
+        self.mark = None;
+    }
+
+    /// Plays the song again from the mark, or from the start of the region
+    pub fn restart(&mut self) {
+        let start = self.song.as_ref().map_or(0, |song| song.region.start);
+        self.restart = Some(self.mark.unwrap_or(start));
+    }
+
+    /// Plays the changed song from the mark or where the player is, or keeps playing the song as
+    /// it was if the changed one is broken
+    pub fn reload(&mut self, player: &Player, changed: Result<Song, String>) -> Result<(), String> {
+        let changed = changed?;
+        let region = changed.region;
+        let position = player.position();
+        self.restart = Some(match self.mark {
+            Some(mark) => mark,
+            // A song which has ended, or has moved out of the region, starts over
+            None if player.is_over() || !region.contains(position) => region.start,
+            None => position,
+        });
+        self.song = Some(changed);
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Denominator used when an exact value does not fit in `u64`
+const APPROXIMATION_DENOMINATOR: u64 = (1 << 24) * 27 * 25 * 7;
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Non-negative rational number used for musical lengths
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Ratio {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl Ratio {
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        if denominator == 0 {
+            return Self::zero();
+        }
+
+        let divisor = gcd(numerator, denominator);
+        Ratio {
+            numerator: numerator / divisor,
+            denominator: denominator / divisor,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Ratio {
+            numerator: 0,
+            denominator: 1,
+        }
+    }
+
+    pub fn from_integer(value: u64) -> Self {
+        Ratio {
+            numerator: value,
+            denominator: 1,
+        }
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        if value.is_nan() || value <= 0.0 {
+            return Self::zero();
+        }
+
+        let numerator = value * APPROXIMATION_DENOMINATOR as f64;
+        if numerator >= u64::MAX as f64 {
+            return Ratio::new(u64::MAX, APPROXIMATION_DENOMINATOR);
+        }
+        Ratio::new(numerator.round() as u64, APPROXIMATION_DENOMINATOR)
+    }
+
+    pub fn numerator(&self) -> u64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u64 {
+        self.denominator
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator == 0
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.to_f64() as f32
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// Largest integer not greater than the value
+    pub fn floor(self) -> Self {
+        Ratio::from_integer(self.numerator / self.denominator)
+    }
+
+    pub fn fract(self) -> Self {
+        Ratio::new(self.numerator % self.denominator, self.denominator)
+    }
+
+    /// Reciprocal of the value, or zero for zero
+    pub fn recip(self) -> Self {
+        if self.is_zero() {
+            return Self::zero();
+        }
+
+        Ratio {
+            numerator: self.denominator,
+            denominator: self.numerator,
+        }
+    }
+
+    /// Returns `self - rhs`, or zero when `rhs` is greater
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        if self <= rhs {
+            Self::zero()
+        } else {
+            self - rhs
+        }
+    }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        let divisor = gcd(self.denominator, rhs.denominator);
+        let denominator = (self.denominator / divisor).checked_mul(rhs.denominator)?;
+        let lhs = self.numerator.checked_mul(denominator / self.denominator)?;
+        let rhs = rhs.numerator.checked_mul(denominator / rhs.denominator)?;
+        Some(Ratio::new(lhs.checked_add(rhs)?, denominator))
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let divisor = gcd(self.denominator, rhs.denominator);
+        let denominator = (self.denominator / divisor).checked_mul(rhs.denominator)?;
+        let lhs = self.numerator.checked_mul(denominator / self.denominator)?;
+        let rhs = rhs.numerator.checked_mul(denominator / rhs.denominator)?;
+        Some(Ratio::new(lhs.checked_sub(rhs)?, denominator))
+    }
+
+    fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let a = gcd(self.numerator, rhs.denominator).max(1);
+        let b = gcd(rhs.numerator, self.denominator).max(1);
+        let numerator = (self.numerator / a).checked_mul(rhs.numerator / b)?;
+        let denominator = (self.denominator / b).checked_mul(rhs.denominator / a)?;
+        Some(Ratio::new(numerator, denominator))
+    }
+}
+
+impl Add for Ratio {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs)
+            .unwrap_or_else(|| Ratio::from_f64(self.to_f64() + rhs.to_f64()))
+    }
+}
+
+impl Sub for Ratio {
+    type Output = Self;
+
+    /// Panics if `rhs` is greater than `self`, as lengths cannot be negative
+    fn sub(self, rhs: Self) -> Self {
+        assert!(rhs <= self, "attempt to subtract with overflow");
+        self.checked_sub(rhs)
+            .unwrap_or_else(|| Ratio::from_f64(self.to_f64() - rhs.to_f64()))
+    }
+}
+
+impl Mul for Ratio {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        self.checked_mul(rhs)
+            .unwrap_or_else(|| Ratio::from_f64(self.to_f64() * rhs.to_f64()))
+    }
+}
+
+impl Div for Ratio {
+    type Output = Self;
+
+    /// Dividing by zero results in zero
+    fn div(self, rhs: Self) -> Self {
+        if rhs.is_zero() {
+            return Self::zero();
+        }
+
+        let reciprocal = rhs.recip();
+        self.checked_mul(reciprocal)
+            .unwrap_or_else(|| Ratio::from_f64(self.to_f64() / rhs.to_f64()))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lhs = self.numerator as u128 * other.denominator as u128;
+        let rhs = other.numerator as u128 * self.denominator as u128;
+        lhs.cmp(&rhs)
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
@@ -3,6 +3,8 @@ pub enum TokenKind {
     Character(char),
     Number(usize),
     BraceString(String),
+    /// Text in double quotes, which keeps its whitespace and may include non-ascii characters
+    QuotedString(String),
 }
 
 impl TokenKind {
@@ -26,6 +28,10 @@ impl TokenKind {
             _ => false,
         }
     }
+
+    pub fn is_quoted_string(&self) -> bool {
+        matches!(self, TokenKind::QuotedString(_))
+    }
 }
 
 use std::fmt;
@@ -36,6 +42,7 @@ impl fmt::Display for TokenKind {
             TokenKind::Character(x) => write!(f, "{}", x),
             TokenKind::Number(x) => write!(f, "{}", x),
             TokenKind::BraceString(x) => write!(f, "{}", x),
+            TokenKind::QuotedString(x) => write!(f, "\"{}\"", x),
         }
     }
 }
@@ -43,15 +50,14 @@ impl fmt::Display for TokenKind {
 pub type Token = (usize, TokenKind);
 
 pub fn tokenize(mml: &str) -> Result<Vec<Token>, String> {
-    let is_ascii = mml.chars().all(|x| x <= '\u{7f}');
-    if !is_ascii {
-        return Err("MML must not include any non-ascii characters".to_string());
-    }
-
     let mut chars = mml.chars().enumerate().peekable();
     let mut tokens = Vec::new();
 
     while let Some((i, byte)) = chars.next() {
+        if !byte.is_ascii() {
+            return Err("MML must not include any non-ascii characters".to_string());
+        }
+
         let token = if '0' <= byte && byte <= '9' {
             let mut number = (byte as u8 - b'0') as usize;
 
@@ -75,18 +81,37 @@ pub fn tokenize(mml: &str) -> Result<Vec<Token>, String> {
             TokenKind::Number(number)
         } else if byte == '{' {
             let mut string = String::new();
+            let mut depth = 0;
 
             loop {
                 let peeked = chars.next();
                 match peeked {
-                    Some((_, '}')) => break TokenKind::BraceString(string),
+                    Some((_, '}')) if depth == 0 => break TokenKind::BraceString(string),
+                    Some((_, x)) if !x.is_ascii() => {
+                        return Err("MML must not include any non-ascii characters".to_string())
+                    }
                     Some((_, x)) if !x.is_whitespace() => {
+                        match x {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => (),
+                        }
                         string.push(x)
                     }
                     Some((_, _)) => (),
                     None => return Err("Unexpected EOF".to_string()),
                 }
             }
+        } else if byte == '"' {
+            let mut string = String::new();
+
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break TokenKind::QuotedString(string),
+                    Some((_, x)) => string.push(x),
+                    None => return Err("Unexpected EOF".to_string()),
+                }
+            }
         } else if 'A' <= byte && byte <= 'Z' {
             TokenKind::Character(byte.to_lowercase().next().unwrap())
         } else if byte.is_whitespace() {
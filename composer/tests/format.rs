@@ -0,0 +1,16 @@
+use composer::*;
+
+#[test]
+fn test_format() {
+    use format::format;
+    use tokenize::tokenize;
+
+    let formatted = |mml: &str| format(&tokenize(mml).unwrap());
+
+    assert_eq!(formatted(""), "");
+    assert_eq!(
+        formatted("#title\"My Song\" #tempo 120 t150 c4 d8 ; @h{0f 8a} e%96 1"),
+        "#TITLE\"My Song\"\n#TEMPO120\nT150C4D8;\n@H{0f8a}E%96 1\n"
+    );
+    assert_eq!(formatted("C#track\"a\"D"), "C\n#TRACK\"a\"\nD\n");
+}
@@ -0,0 +1,125 @@
+//! Property tests feeding random MML to the whole pipeline.
+//!
+//! Each property runs 256 cases by default. Set `PROPTEST_CASES` for a longer run.
+
+use composer::*;
+use proptest::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Low enough to render a few seconds of every case quickly
+const SAMPLE_RATE: f32 = 4000.0;
+const RENDERED_SAMPLES: usize = 12000;
+/// Longest a case may take to parse and render, however long its repeats make the song
+const BUDGET: Duration = Duration::from_secs(10);
+
+/// Numbers are small so that the generated songs stay short, and often zero or one to hit the edge cases
+const NUMBER: &str = "(0|1|[0-9]{1,3})";
+
+fn pattern(pattern: &str) -> BoxedStrategy<String> {
+    proptest::string::string_regex(&pattern.replace('N', NUMBER))
+        .unwrap()
+        .boxed()
+}
+
+fn command() -> impl Strategy<Value = String> {
+    let notes = (4, pattern("[a-g][+-]?(%?N)?\\.?(&N)?"));
+    let others = [
+        pattern("rN?"),
+        pattern("[<>]"),
+        pattern("\\((([a-g][+-]?)|[<>]){0,4}\\)N?"),
+        pattern("[tv]N"),
+        pattern("l%?N"),
+        pattern("[tv][~^]N,N"),
+        pattern("@N"),
+        pattern("@dN,N"),
+        pattern("@[gtvpkrs]N"),
+        pattern("@eN,N,N,N"),
+        pattern("@fdN,N"),
+        pattern("@f[lh]N(,N)?"),
+        pattern("@fqN,N(,N){0,2}"),
+        pattern("@f[em]N,N,N,N"),
+        pattern("@f[bs]N"),
+        pattern("@foN,N"),
+        pattern("@f[rcp]N,N,N"),
+        pattern("@ffN,N,N,N"),
+        pattern("@f0"),
+        pattern("@aN(,N){0,2}"),
+        pattern("@a[0-9]{1,10}(,[0-2](,[0-9]{1,10})?)?\\([a-g]{1,3}\\)N?"),
+        pattern("@jN,N(,N)?"),
+        pattern("@sN,N"),
+        pattern("@h\\{[0-9a-f]{0,16}\\}"),
+        pattern("@n\\{[A-Za-z0-9+/]{0,8}\\}"),
+        pattern("@\\(@N(,@N)?\\)"),
+        pattern("@b\\{[ab]\\}\\((@fdN,N|@flN)?\\)"),
+        pattern("@x\\{[ab]\\}N"),
+        pattern("#(title|composer|track)\"[a-z ]{0,4}\""),
+        pattern("#tempoN"),
+    ];
+    let weighted =
+        std::iter::once(notes).chain(others.iter().cloned().map(|strategy| (1, strategy)));
+    proptest::strategy::Union::new_weighted(weighted.collect())
+}
+
+fn track() -> impl Strategy<Value = String> {
+    let commands = prop::collection::vec(command(), 0..8).prop_map(|commands| commands.concat());
+    commands.prop_recursive(4, 64, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 1..4).prop_map(|tracks| tracks.concat()),
+            // Large repeats now and then, which the parser has to keep from growing without bound
+            (
+                inner.clone(),
+                prop_oneof![4 => 0..4usize, 1 => 0..1000usize]
+            )
+                .prop_map(|(track, n)| format!("[{}]{}", track, n)),
+            (inner, pattern("N?")).prop_map(|(track, n)| format!("{{{}}}{}", track, n)),
+        ]
+    })
+}
+
+fn mml() -> impl Strategy<Value = String> {
+    prop::collection::vec(track(), 1..4).prop_map(|tracks| tracks.join(";"))
+}
+
+fn render(mml: &str) {
+    let started = Instant::now();
+    let tokens = match tokenize::tokenize(mml) {
+        Ok(tokens) => tokens,
+        Err(_) => return,
+    };
+    let tracks = match parse::parse(&tokens) {
+        Ok(tracks) => tracks,
+        Err(_) => return,
+    };
+    let generator = generate::Generator::new(SAMPLE_RATE, &tracks);
+    for sample in generator.take(RENDERED_SAMPLES) {
+        assert!(sample.is_finite());
+    }
+    assert!(started.elapsed() < BUDGET, "took {:?}", started.elapsed());
+}
+
+proptest! {
+    #[test]
+    fn test_parse_any_string(mml in "[ -~]{0,200}") {
+        if let Ok(tokens) = tokenize::tokenize(&mml) {
+            let _ = parse::parse(&tokens);
+        }
+    }
+
+    #[test]
+    fn test_render_mml(mml in mml()) {
+        render(&mml);
+    }
+
+    #[test]
+    fn test_format_keeps_tokens(mml in "[ -~]{0,200}") {
+        if let Ok(tokens) = tokenize::tokenize(&mml) {
+            let formatted = format::format(&tokens);
+            let reformatted = tokenize::tokenize(&formatted).unwrap();
+            let kinds = |tokens: &[tokenize::Token]| {
+                tokens.iter().map(|(_, kind)| kind.clone()).collect::<Vec<_>>()
+            };
+            prop_assert_eq!(kinds(&reformatted), kinds(&tokens));
+            prop_assert_eq!(format::format(&reformatted), formatted);
+        }
+    }
+}
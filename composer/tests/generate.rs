@@ -9,6 +9,51 @@ fn assert_float_eq(a: f32, b: f32) {
     }
 }
 
+#[test]
+fn test_note_length_to_ratio() {
+    use generate::note_length_to_ratio;
+    use parse::NoteLength::*;
+    use ratio::Ratio;
+
+    assert_eq!(
+        note_length_to_ratio(&[Length(12), Length(12), Length(12)], Ratio::new(1, 8)),
+        Ratio::new(1, 4)
+    );
+    assert_eq!(
+        note_length_to_ratio(&[Ticks(48), Dot, DefaultLength], Ratio::new(1, 5)),
+        Ratio::new(1, 4) + Ratio::new(1, 8) + Ratio::new(1, 5)
+    );
+    assert_eq!(
+        note_length_to_ratio(&[Length(0)], Ratio::new(1, 8)),
+        Ratio::zero()
+    );
+}
+
+#[test]
+fn test_measure_track() {
+    use generate::measure_track;
+    use parse::parse;
+    use ratio::Ratio;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("C4L16[DE]3{CDE}8R%96").unwrap()).unwrap();
+    assert_eq!(
+        measure_track(&tracks.tracks[0], Ratio::new(1, 8)),
+        (Ratio::new(5, 4), Ratio::new(1, 16))
+    );
+}
+
+#[test]
+fn test_count_notes() {
+    use generate::count_notes;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("C4R[D(EG)]3{CDE}8@A8(CEG)").unwrap()).unwrap();
+    assert_eq!(count_notes(&tracks.tracks[0]), 1 + 3 * 3 + 3 + 3);
+    assert_eq!(count_notes(&[]), 0);
+}
+
 #[test]
 fn test_note_length_to_float() {
     use generate::note_length_to_float;
@@ -78,6 +123,239 @@ fn test_note_queue() {
     assert_eq!(queue.next_before(10.0), None);
 }
 
+#[test]
+fn test_effects_queue() {
+    use generate::effects::{EffectEvent::*, EffectsQueue};
+
+    let mut queue = EffectsQueue::new(vec![(f32::NAN, Clear(2)), (1.0, Clear(0))]);
+    assert!(queue.next_before(2.0).is_some());
+
+    let mut queue = EffectsQueue::new(vec![(1.0, Clear(0)), (0.5, Clear(1)), (1.0, Clear(2))]);
+    assert_eq!(queue.next_before(0.1), None);
+    assert_eq!(queue.next_before(2.0), Some(Clear(1)));
+    assert_eq!(queue.next_before(2.0), Some(Clear(0)));
+    assert_eq!(queue.next_before(2.0), Some(Clear(2)));
+    assert_eq!(queue.next_before(2.0), None);
+}
+
+#[test]
+fn test_effect_chain() {
+    use generate::effects::EffectChain;
+    use parse::tone::Effect;
+
+    let delay = |delay| Effect::Delay {
+        delay,
+        feedback: 0.5,
+    };
+    let mut chain = EffectChain::default();
+    assert_float_eq(chain.apply(1.0), 1.0);
+
+    // 2 samples of delay
+    chain.set(&delay(2.0), 1.0);
+    assert_float_eq(chain.apply(1.0), 1.0);
+    assert_float_eq(chain.apply(0.0), 0.0);
+    assert_float_eq(chain.apply(0.0), 0.5);
+
+    // Changing the delay keeps the sound in it instead of adding another delay
+    chain.set(&delay(1.0), 1.0);
+    assert_float_eq(chain.apply(0.0), 0.25);
+    assert_float_eq(chain.apply(0.0), 0.125);
+
+    chain.clear();
+    assert_float_eq(chain.apply(0.5), 0.5);
+}
+
+#[test]
+fn test_biquad() {
+    use generate::effects::{Biquad, Effector, FilterEnvelope};
+    use parse::tone::FilterKind::*;
+    use std::f32::consts::PI;
+
+    // Peak amplitude of a sine wave after the filter has settled
+    let amplitude = |filter: &mut dyn Effector, frequency: f32| {
+        let samples: Vec<_> = (0..8000)
+            .map(|i| filter.apply((2.0 * PI * frequency * i as f32 / 44100.0).sin()))
+            .collect();
+        samples[4000..].iter().fold(0.0f32, |a, x| a.max(x.abs()))
+    };
+    let filter = |kind, gain| Biquad::new(kind, 1000.0, 0.707, gain, 44100.0);
+
+    assert!((amplitude(&mut filter(LowPass, 1.0), 100.0) - 1.0).abs() < 0.01);
+    assert!(amplitude(&mut filter(LowPass, 1.0), 10000.0) < 0.02);
+    assert!(amplitude(&mut filter(HighPass, 1.0), 100.0) < 0.02);
+    assert!(amplitude(&mut filter(BandPass, 1.0), 1000.0) > 0.99);
+    assert!(amplitude(&mut filter(BandPass, 1.0), 10000.0) < 0.2);
+    assert!(amplitude(&mut filter(Notch, 1.0), 1000.0) < 0.01);
+    assert!((amplitude(&mut filter(AllPass, 1.0), 1000.0) - 1.0).abs() < 0.01);
+    assert!((amplitude(&mut filter(Peak, 2.0), 1000.0) - 2.0).abs() < 0.02);
+    assert!((amplitude(&mut filter(LowShelf, 2.0), 50.0) - 2.0).abs() < 0.02);
+    assert!((amplitude(&mut filter(HighShelf, 0.5), 15000.0) - 0.5).abs() < 0.02);
+
+    // The cut off frequency starts low when a note starts
+    let envelope = || FilterEnvelope::new(100.0, 20000.0, 1.0, 0.707, 44100.0);
+    assert!(amplitude(&mut envelope(), 5000.0) > 0.9);
+    let mut triggered = envelope();
+    triggered.trigger();
+    assert!(amplitude(&mut triggered, 5000.0) < 0.01);
+}
+
+#[test]
+fn test_reverb() {
+    use generate::effects::{Effector, Reverb};
+    use parse::tone::Effect;
+
+    let mut dry = Reverb::new(0.5, 0.5, 0.0, 44100.0);
+    assert_float_eq(dry.apply(0.7), 0.7);
+    assert_float_eq(dry.apply(-0.2), -0.2);
+
+    let mut reverb = Reverb::new(0.5, 0.5, 1.0, 44100.0);
+    reverb.apply(1.0);
+    let tail: Vec<_> = (0..44100).map(|_| reverb.apply(0.0)).collect();
+    let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |a, x| a.max(x.abs()));
+    // The tail rings after the impulse and decays
+    assert!(peak(&tail[..4000]) > 1e-3);
+    assert!(peak(&tail) <= 1.0);
+    assert!(peak(&tail[40000..]) < peak(&tail[..4000]));
+
+    let room = |room_size| Effect::Reverb {
+        room_size,
+        damping: 0.5,
+        mix: 0.0,
+    };
+    reverb.update(&room(0.9), 44100.0);
+    assert_float_eq(reverb.apply(0.3), 0.3);
+}
+
+#[test]
+fn test_modulated_delay() {
+    use generate::effects::{Effector, ModulatedDelay, Phaser};
+    use parse::tone::Effect;
+
+    let flanger = |feedback| Effect::Flanger {
+        rate: 0.0,
+        depth: 0.0,
+        feedback,
+        mix: 1.0,
+    };
+
+    // The delay of 1.5 samples is interpolated
+    let mut flanger_a = ModulatedDelay::new(&flanger(0.0), 1500.0);
+    for (&input, &expected) in [1.0, 0.0, 0.0, 0.0].iter().zip(&[0.0, 0.5, 0.5, 0.0]) {
+        assert_float_eq(flanger_a.apply(input), expected);
+    }
+
+    let mut flanger_b = ModulatedDelay::new(&flanger(0.5), 1000.0);
+    for (&input, &expected) in [1.0, 0.0, 0.0, 0.0].iter().zip(&[0.0, 1.0, 0.5, 0.25]) {
+        assert_float_eq(flanger_b.apply(input), expected);
+    }
+
+    let chorus = Effect::Chorus {
+        rate: 1.0,
+        depth: 0.005,
+        mix: 0.0,
+    };
+    let mut chorus = ModulatedDelay::new(&chorus, 44100.0);
+    assert_float_eq(chorus.apply(0.7), 0.7);
+    assert_float_eq(chorus.apply(-0.2), -0.2);
+
+    // All pass filters keep the energy of an impulse
+    let mut phaser = Phaser::new(0.0, 0.5, 1.0, 44100.0);
+    let energy: f32 = (0..44100)
+        .map(|i| phaser.apply(if i == 0 { 1.0 } else { 0.0 }).powi(2))
+        .sum();
+    assert!((energy - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_lo_fi_effects() {
+    use generate::effects::{BitCrusher, Distortion, Effector, RateReducer};
+
+    let mut distortion = Distortion::new(10.0, 1.0);
+    assert_float_eq(distortion.apply(0.0), 0.0);
+    assert!(distortion.apply(0.5) > 0.99);
+    assert!(distortion.apply(-0.5) < -0.99);
+    let mut dry = Distortion::new(10.0, 0.0);
+    assert_float_eq(dry.apply(0.5), 0.5);
+
+    let mut crusher = BitCrusher::new(2);
+    assert_float_eq(crusher.apply(0.3), 0.5);
+    assert_float_eq(crusher.apply(-0.2), 0.0);
+    assert_float_eq(crusher.apply(-0.8), -1.0);
+
+    // Holds every fourth sample
+    let mut reducer = RateReducer::new(250.0, 1000.0);
+    let output: Vec<_> = (0..8).map(|i| reducer.apply(i as f32)).collect();
+    assert_eq!(output, vec![0.0, 0.0, 0.0, 0.0, 4.0, 4.0, 4.0, 4.0]);
+}
+
+#[test]
+fn test_dynamics() {
+    use generate::effects::{Compressor, Effector, Limiter};
+
+    let mut compressor = Compressor::new(0.5, 4.0, 0.0, 0.1, 1000.0);
+    assert_float_eq(compressor.apply(0.25), 0.25);
+    // 2 times the threshold is compressed to 2^(1/4) times
+    assert_float_eq(compressor.apply(1.0), 0.5 * 2.0f32.powf(0.25));
+    assert_float_eq(compressor.apply(-1.0), -0.5 * 2.0f32.powf(0.25));
+
+    let mut limiter = Limiter::new(1.0, 1000.0);
+    let latency = limiter.latency();
+    let input: Vec<_> = (0..100).map(|i| if i == 50 { 4.0 } else { 0.5 }).collect();
+    let output: Vec<_> = input
+        .iter()
+        .chain(std::iter::repeat_n(&0.0, latency))
+        .map(|&x| limiter.apply(x))
+        .skip(latency)
+        .collect();
+    assert!(output.iter().all(|x| x.abs() <= 1.0 + 1e-6));
+    assert_float_eq(output[50], 1.0);
+    // The gain goes down only shortly before the peak
+    assert_float_eq(output[10], 0.5);
+    assert_float_eq(output[50 - latency - 1], 0.5);
+}
+
+#[test]
+fn test_master() {
+    use generate::master::{loudness, normalize, peak, MasterOptions, Normalization};
+    use generate::Generator;
+    use parse::parse;
+    use std::f32::consts::PI;
+    use tokenize::tokenize;
+
+    let sine: Vec<_> = (0..48000)
+        .map(|i| (2.0 * PI * 997.0 * i as f32 / 48000.0).sin())
+        .collect();
+    assert!((loudness(&sine, 48000.0) + 3.01).abs() < 0.05);
+    assert_eq!(loudness(&[0.0; 48000], 48000.0), f32::NEG_INFINITY);
+
+    let mut normalized = sine.clone();
+    normalize(&mut normalized, Normalization::Loudness(-23.0), 48000.0);
+    assert!((loudness(&normalized, 48000.0) + 23.0).abs() < 0.05);
+    normalize(&mut normalized, Normalization::Peak(0.5), 48000.0);
+    assert_float_eq(peak(&normalized), 0.5);
+
+    let tracks = parse(&tokenize("CDE;EFG").unwrap()).unwrap();
+    let render = |master| Generator::with_master(44100.0, &tracks, master).render();
+    let clipped = render(MasterOptions {
+        limiter: false,
+        ..MasterOptions::default()
+    });
+    // The limiter does not delay the output
+    assert_eq!(render(MasterOptions::default()), clipped);
+
+    let loud = render(MasterOptions {
+        gain: 4.0,
+        ..MasterOptions::default()
+    });
+    assert_eq!(loud.len(), clipped.len());
+    assert!(peak(&loud) <= 1.0);
+    let peak_normalized = render(MasterOptions {
+        normalization: Some(Normalization::Peak(0.8)),
+        ..MasterOptions::default()
+    });
+    assert_float_eq(peak(&peak_normalized), 0.8);
+}
+
 #[test]
 fn test_tone() {
     use generate::ToneKind;
@@ -89,4 +367,660 @@ fn test_tone() {
     let pcm_tone = ToneKind::PCMTone(Arc::new(vec![0.0, 1.0, 2.0, 3.0, 4.0]));
     assert_float_eq(pcm_tone.sample(0.2, 1.5), 1.0);
     assert_float_eq(pcm_tone.sample(0.2, 7.5), 2.0);
+
+    let noise_tone = ToneKind::NoiseTone(|seed, _, _| seed as f32, 3);
+    assert_float_eq(noise_tone.sample(10.0, 20.0), 3.0);
+}
+
+#[test]
+fn test_tone_modify() {
+    use generate::{effects::EffectEvent, EffectSink, Tone, ToneKind, ToneRegistry};
+    use parse::{tone::Effect, ToneModifier};
+
+    let oscillators = [ToneKind::FnTone(|_, _| 0.0), ToneKind::FnTone(|_, _| 1.0)];
+    let mut registry = ToneRegistry::new(&oscillators, Vec::new());
+    let mut effects = Vec::new();
+    let mut chains = Vec::new();
+    let mut sink = EffectSink::new(2.0, &mut chains, &mut effects);
+    let mut tone = Tone::new(registry.oscillator(0));
+
+    tone.modify(
+        &ToneModifier::DefinePCMTone(vec![0.5]),
+        &mut registry,
+        &mut sink,
+    );
+    assert_eq!(registry.pcm(0), Some(std::sync::Arc::new(vec![0.5])));
+    assert_eq!(registry.pcm(1), None);
+    assert_eq!(registry.oscillator(1), oscillators[1]);
+    assert_eq!(registry.oscillator(9), oscillators[0]);
+    assert_eq!(registry.oscillator_index(1), 1);
+    assert_eq!(registry.oscillator_index(9), 0);
+    assert_float_eq(registry.oscillator(1).sample(1.0, 0.0), 1.0);
+
+    let delay = Effect::Delay {
+        delay: 0.1,
+        feedback: 0.5,
+    };
+    let reverb = Effect::Reverb {
+        room_size: 0.5,
+        damping: 0.5,
+        mix: 0.5,
+    };
+    // A tone with the same effects as another shares its chain
+    let mut other = Tone::new(registry.oscillator(0));
+    tone.modify(&ToneModifier::Effect(delay), &mut registry, &mut sink);
+    other.modify(&ToneModifier::Effect(delay), &mut registry, &mut sink);
+    other.modify(&ToneModifier::Effect(reverb), &mut registry, &mut sink);
+    tone.modify(&ToneModifier::ClearEffects, &mut registry, &mut sink);
+    tone.modify(&ToneModifier::Effect(delay), &mut registry, &mut sink);
+    assert_eq!(chains, vec![vec![delay], vec![delay, reverb]]);
+    assert_eq!(effects.len(), 3);
+    assert_float_eq(effects[0].0, 2.0);
+    assert_eq!(effects[0].1, EffectEvent::Set(1, delay));
+    assert_eq!(effects[1].1, EffectEvent::Set(2, delay));
+    assert_eq!(effects[2].1, EffectEvent::Set(2, reverb));
+}
+
+#[test]
+fn test_effect_chains() {
+    use generate::effects::EffectEvent;
+    use generate::{parse_track, Generator, ToneKind, TrackState};
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let oscillators = [ToneKind::FnTone(|_, _| 0.0)];
+    let chains = |mml: &str| {
+        let mut state = TrackState::new(44100.0, &oscillators, Vec::new());
+        parse_track(
+            &parse(&tokenize(mml).unwrap()).unwrap().tracks[0],
+            &mut state,
+        );
+        let events = state.drain_effects_queue();
+        let chains = events.iter().map(|(_, event)| match event {
+            EffectEvent::Set(chain, _) | EffectEvent::Clear(chain) => *chain,
+            EffectEvent::Trigger(chain) => *chain,
+            EffectEvent::Send(..) => 0,
+        });
+        chains.max().unwrap_or(0)
+    };
+    // A tone made again with the same effects does not add a chain
+    assert_eq!(chains("L64[@(@4@FR500,500,500)C]200"), 1);
+    assert_eq!(chains("[@(@FD100,300|@FD100,300)C@(@FD200,300)C]50"), 2);
+    assert_eq!(chains("@(@FD100,300)C@(@FD100,300@FD200,300)C"), 2);
+    assert_eq!(chains("[@(@FD100,300)C@F0]50"), 1);
+
+    let render = |mml: &str| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        Generator::new(44100.0, &tracks).render()
+    };
+    assert_eq!(
+        render("L16[@(@4@FR500,500,500)C]8"),
+        render("L16@(@4@FR500,500,500)[C]8")
+    );
+
+    // @F0 outside of @(...) removes the effects of the tones too
+    let cleared = render("@(@4@FD150,300)C@F0C");
+    assert_ne!(cleared, render("@(@4@FD150,300)CC"));
+    let dry = render("@(@4)R@F0C");
+    assert_eq!(cleared.len(), dry.len());
+    assert_eq!(cleared[11025..], dry[11025..]);
+}
+
+#[test]
+fn test_tone_effects() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let render = |mml: &str| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        Generator::new(44100.0, &tracks).collect::<Vec<_>>()
+    };
+
+    // An effect inside a tone of @() only affects the tone
+    assert_eq!(render("@(@5@FH8000|@3)C"), render("@(@5@FH8000)C;@(@3)C"));
+    assert_ne!(render("@(@5@FH8000|@3)C"), render("@(@5,@3)@FH8000C"));
+    // Clearing effects
+    assert_eq!(render("@FH8000@F0C"), render("C"));
+    assert_ne!(render("@FH8000C@F0C"), render("@FH8000CC"));
+    // Filters of different types are separate effects
+    assert_ne!(render("@FL2000@FH100C"), render("@FH100C"));
+    assert_eq!(render("@FL2000@FL100C"), render("@FL100C"));
+    // A bus applies its effects to what the tracks send to it, in addition to the tracks themselves
+    assert_eq!(render("@B{a}(@FL1000);@X{a}100C"), render("C;@FL1000C"));
+    assert_eq!(render("@X{a}100C;@B{a}(@FL1000)"), render("C;@FL1000C"));
+    assert_eq!(render("@B{a}(@FL1000);@X{a}0C"), render("C"));
+    // A send to a bus which is never declared is dropped instead of doubling the track
+    assert_eq!(render("@X{a}100V50C"), render("V50C"));
+    assert_eq!(render("@B{b}(@FL1000);@X{a}100C"), render("C"));
+    assert_ne!(
+        render("@B{a}(@FL1000);@X{a}100C@X{a}0C"),
+        render("C;@FL1000CR")
+    );
+    // The filter envelope starts again at every note
+    assert_ne!(render("@FE200,8000,300,700CC"), render("@FL8000CC"));
+}
+
+#[test]
+fn test_noise() {
+    use generate::tones::noise;
+
+    assert_eq!(noise(1, 440.0, 0.5), noise(1, 880.0, 0.5));
+    assert_ne!(noise(1, 440.0, 0.5), noise(2, 440.0, 0.5));
+    assert_ne!(noise(1, 440.0, 0.5), noise(1, 440.0, 0.6));
+}
+
+#[test]
+fn test_pitched_noise() {
+    use generate::tones::{lfsr_long, lfsr_short, pitched_noise};
+
+    // A value is held for 1/16 of the period
+    assert_eq!(
+        pitched_noise(1, 100.0, 0.0),
+        pitched_noise(1, 100.0, 0.0006)
+    );
+    assert_ne!(
+        pitched_noise(1, 100.0, 0.0),
+        pitched_noise(1, 200.0, 0.0006)
+    );
+    assert_eq!(pitched_noise(1, 100.0, 0.0), pitched_noise(1, 200.0, 0.0));
+
+    let steps = |tone: fn(u64, f32, f32) -> f32| {
+        (0..200)
+            .map(|step| tone(0, 1.0, (step as f32 + 0.5) / 16.0))
+            .collect::<Vec<_>>()
+    };
+    let short = steps(lfsr_short);
+    assert_eq!(short[..93], short[93..186]);
+    assert!(short.iter().all(|&x| x == 1.0 || x == -1.0));
+    let long = steps(lfsr_long);
+    assert_ne!(long[..93], long[93..186]);
+}
+
+#[test]
+fn test_deterministic_render() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let render = |mml: &str| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        Generator::new(44100.0, &tracks).collect::<Vec<_>>()
+    };
+
+    let mml = "@6L16CDEF;@4L8C>C<@K1CDEF";
+    assert_eq!(render(mml), render(mml));
+    assert_eq!(render("@R1@6C"), render("@R1@6C"));
+    assert_ne!(render("@R1@6C"), render("@R2@6C"));
+}
+
+#[test]
+fn test_mute_and_solo() {
+    use generate::master::MasterOptions;
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let render = |mml: &str, muted: &[usize], soloed: &[usize]| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        let mut generator = Generator::new(44100.0, &tracks);
+        for &track in muted {
+            generator.set_muted(track, true);
+        }
+        for &track in soloed {
+            generator.set_soloed(track, true);
+        }
+        generator.render()
+    };
+
+    let mml = "C;E;@B{a}(@FD100,500)@X{a}100G";
+    assert_eq!(render(mml, &[1, 2], &[]), render("C;R;R", &[], &[]));
+    assert_eq!(render(mml, &[], &[1]), render("R;E;R", &[], &[]));
+    assert_eq!(render(mml, &[0], &[0, 2]), render(mml, &[0, 1], &[]));
+    // Sends of a muted track are muted too
+    assert_eq!(render(mml, &[2], &[]), render("C;E;R", &[], &[]));
+
+    let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_eq!(generator.tracks(), 3);
+    assert!(generator.is_audible(0));
+    assert!(!generator.is_audible(3));
+
+    // The stems add up to the whole song unless it is limited
+    let master = MasterOptions {
+        gain: 0.1,
+        ..MasterOptions::default()
+    };
+    let stems: Vec<_> = Generator::stems(44100.0, &tracks, master)
+        .into_iter()
+        .map(Generator::render)
+        .collect();
+    assert_eq!(stems.len(), 3);
+    let whole = Generator::with_master(44100.0, &tracks, master).render();
+    for (i, &sample) in whole.iter().enumerate() {
+        assert_float_eq(stems.iter().map(|stem| stem[i]).sum(), sample);
+    }
+}
+
+#[test]
+fn test_markers() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("T120L8CD@M{chorus}EF@M{end};@M{bass}L2C").unwrap()).unwrap();
+    let generator = Generator::new(8000.0, &tracks);
+    let markers: Vec<_> = generator
+        .markers()
+        .iter()
+        .map(|(name, position)| (name.as_str(), *position))
+        .collect();
+    assert_eq!(markers, vec![("bass", 0.0), ("chorus", 0.5), ("end", 1.0)]);
+    assert_eq!(generator.marker("chorus"), Some(0.5));
+    assert_eq!(generator.marker("verse"), None);
+}
+
+#[test]
+fn test_seek() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let sample_rate = 8000.0;
+    let seeked = |mml: &str, seconds: f32| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        let whole: Vec<_> = Generator::new(sample_rate, &tracks).collect();
+        let mut generator = Generator::new(sample_rate, &tracks);
+        generator.seek(seconds);
+        let rest: Vec<_> = generator.collect();
+        let skipped = (seconds * sample_rate) as usize;
+        assert_eq!(rest.len(), whole.len() - skipped);
+        for (&a, &b) in rest.iter().zip(whole[skipped..].iter()) {
+            assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+        }
+    };
+
+    // Within the pre-roll, and after notes are skipped without being synthesized
+    let mml = "T120L4CDEFGAB<CDEFGAB;L1<CEGC";
+    seeked(mml, 1.0);
+    seeked(mml, 2.5);
+    // The tails of the delay and the reverb before the position are mixed in
+    seeked(
+        "T120L4@FD250,300[CDEFGAB]2;@B{a}(@FR500,300,500)@X{a}50L2[CG]4",
+        4.25,
+    );
+
+    // Seeking past the end leaves nothing to play
+    let tracks = parse(&tokenize("C").unwrap()).unwrap();
+    let mut generator = Generator::new(sample_rate, &tracks);
+    generator.seek(10.0);
+    assert_eq!(generator.next(), None);
+}
+
+#[test]
+fn test_metadata() {
+    use generate::tags::{riff_info, vorbis_comments};
+    use generate::Generator;
+    use parse::{parse, Metadata};
+    use tokenize::tokenize;
+
+    let render = |mml: &str| Generator::new(44100.0, &parse(&tokenize(mml).unwrap()).unwrap());
+
+    // The tempo in the header starts the first track, and later tracks carry it on as T does
+    assert_eq!(render("#TEMPO60C;D").render(), render("T60C;D").render());
+    assert_eq!(
+        render("#TEMPO60T120C;D").render(),
+        render("T120C;D").render()
+    );
+
+    let metadata = Metadata {
+        title: Some("Song".to_string()),
+        composer: Some("Me".to_string()),
+        tempo: None,
+    };
+    assert_eq!(
+        riff_info(&metadata),
+        b"LIST\x1e\x00\x00\x00INFOINAM\x05\x00\x00\x00Song\x00\x00IART\x03\x00\x00\x00Me\x00\x00"
+    );
+    assert_eq!(riff_info(&Metadata::default()), b"");
+    assert_eq!(
+        vorbis_comments(&metadata),
+        b"\x08\x00\x00\x00composer\x02\x00\x00\x00\x0a\x00\x00\x00TITLE=Song\x09\x00\x00\x00ARTIST=Me"
+    );
+
+    let plain = render("C").into_riff().unwrap();
+    let tagged = render("#TITLE\"Song\"#COMPOSER\"Me\"C")
+        .into_riff()
+        .unwrap();
+    let info = riff_info(&metadata);
+    assert_eq!(tagged.len(), plain.len() + info.len());
+    assert_eq!(&tagged[36..36 + info.len()], info.as_slice());
+    assert_eq!(tagged[36 + info.len()..], plain[36..]);
+}
+
+#[test]
+fn test_wav() {
+    use generate::wav::{Quantizer, SampleFormat, WavOptions};
+    use generate::Generator;
+    use parse::parse;
+    use std::io::{Cursor, Seek, Write};
+    use tokenize::tokenize;
+
+    let mut quantizer = Quantizer::new(16, false);
+    assert_eq!(quantizer.quantize(1.0), 32767);
+    assert_eq!(quantizer.quantize(-2.0), -32768);
+    assert_eq!(quantizer.quantize(0.3), 9830);
+    assert_eq!(Quantizer::new(24, false).quantize(-1.0), -8388607);
+    let mut dithered = Quantizer::new(16, true);
+    let values: Vec<_> = (0..1000).map(|_| dithered.quantize(0.3)).collect();
+    assert!(values.iter().all(|x| (x - 9830).abs() <= 1));
+    assert!(values.iter().any(|&x| x != 9830));
+
+    let tracks = parse(&tokenize("CDE").unwrap()).unwrap();
+    let samples = Generator::new(8000.0, &tracks).render();
+    let u32_at = |wav: &[u8], at: usize| {
+        u32::from_le_bytes([wav[at], wav[at + 1], wav[at + 2], wav[at + 3]]) as usize
+    };
+    let wav = |format| {
+        let options = WavOptions {
+            format,
+            dither: false,
+        };
+        Generator::new(8000.0, &tracks)
+            .into_riff_with(options)
+            .unwrap()
+    };
+
+    let int16 = wav(SampleFormat::Int16);
+    assert_eq!(int16.len(), 44 + samples.len() * 2);
+    assert_eq!(u32_at(&int16, 4), int16.len() - 8);
+    assert_eq!(u32_at(&int16, 40), samples.len() * 2);
+
+    let int24 = wav(SampleFormat::Int24);
+    assert_eq!(u32_at(&int24, 40), samples.len() * 3);
+    assert_eq!(int24[32], 3); // Block Align
+    assert_eq!(int24.len() % 2, 0);
+
+    let float32 = wav(SampleFormat::Float32);
+    assert_eq!(&float32[20..22], &[3, 0]);
+    assert_eq!(&float32[38..42], b"fact");
+    assert_eq!(u32_at(&float32, 46), samples.len());
+    let written: Vec<_> = float32[58..]
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+    assert_eq!(written, samples);
+
+    // The file can be written after other data
+    let mut cursor = Cursor::new(Vec::new());
+    cursor.write_all(b"head").unwrap();
+    let options = WavOptions::default();
+    let mut cursor = Generator::new(8000.0, &tracks)
+        .write_wav(cursor, options)
+        .unwrap();
+    assert_eq!(cursor.stream_position().unwrap(), 4 + int16.len() as u64);
+    assert_eq!(&cursor.into_inner()[4..], int16.as_slice());
+}
+
+#[test]
+fn test_flac() {
+    use generate::flac::FlacOptions;
+    use generate::wav::Quantizer;
+    use generate::Generator;
+    use parse::parse;
+    use std::io::Cursor;
+    use tokenize::tokenize;
+
+    let encode = |mml: &str, bits| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        let options = FlacOptions {
+            bits,
+            dither: false,
+        };
+        let flac = Generator::new(8000.0, &tracks)
+            .write_flac(Cursor::new(Vec::new()), options)
+            .unwrap()
+            .into_inner();
+        let samples = Generator::new(8000.0, &tracks).render();
+        (flac, samples)
+    };
+
+    // Noise, drums and silence cover every kind of subframe, and more than 128 frames need longer frame numbers
+    let mml = "#TITLE\"Song\"@4CDE@6L16CDE@K1CDEFGT30[R1]10C";
+    for bits in [16, 24, 12] {
+        let (flac, samples) = encode(mml, bits);
+        let mut reader = claxon::FlacReader::new(flac.as_slice()).unwrap();
+        assert_eq!(reader.streaminfo().bits_per_sample, bits as u32);
+        assert_eq!(reader.streaminfo().samples, Some(samples.len() as u64));
+        assert_eq!(reader.get_tag("TITLE").next(), Some("Song"));
+
+        let mut quantizer = Quantizer::new(bits, false);
+        let expected: Vec<_> = samples.iter().map(|&x| quantizer.quantize(x)).collect();
+        let decoded: Vec<_> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    // Smaller than 16-bit PCM
+    let (flac, samples) = encode("CDEFGAB", 16);
+    assert!(flac.len() < samples.len() * 2);
+    let tracks = parse(&tokenize("C").unwrap()).unwrap();
+    let options = FlacOptions {
+        bits: 7,
+        dither: false,
+    };
+    let result = Generator::new(8000.0, &tracks).write_flac(Cursor::new(Vec::new()), options);
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn test_opus() {
+    use generate::opus::OpusOptions;
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("CDE").unwrap()).unwrap();
+    let opus = Generator::new(48000.0, &tracks)
+        .write_opus(Vec::new(), OpusOptions::default())
+        .unwrap();
+    assert_eq!(&opus[..4], b"OggS");
+    assert_eq!(&opus[28..36], b"OpusHead");
+
+    let result = Generator::new(44100.0, &tracks).write_opus(Vec::new(), OpusOptions::default());
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "vorbis")]
+#[test]
+fn test_vorbis() {
+    use generate::vorbis::VorbisOptions;
+    use generate::Generator;
+    use lewton::inside_ogg::OggStreamReader;
+    use parse::parse;
+    use std::io::Cursor;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("CDE").unwrap()).unwrap();
+    let vorbis = Generator::new(44100.0, &tracks)
+        .write_vorbis(Vec::new(), VorbisOptions::default())
+        .unwrap();
+    assert_eq!(&vorbis[..4], b"OggS");
+    assert_eq!(&vorbis[28..35], b"\x01vorbis");
+
+    let mut reader = OggStreamReader::new(Cursor::new(vorbis)).unwrap();
+    assert_eq!(reader.ident_hdr.audio_channels, 1);
+    assert_eq!(reader.ident_hdr.audio_sample_rate, 44100);
+    let mut length = 0;
+    while let Some(samples) = reader.read_dec_packet_itl().unwrap() {
+        length += samples.len();
+    }
+    assert_eq!(length, Generator::new(44100.0, &tracks).render().len());
+}
+
+#[test]
+fn test_ramp() {
+    use generate::Ramp;
+    use parse::RampCurve;
+
+    let linear = Ramp::new(60.0, 120.0, 1.0, RampCurve::Linear);
+    assert_float_eq(linear.integrate_reciprocal(1.0), 2.0f32.ln() / 60.0);
+    assert_float_eq(
+        linear.integrate_reciprocal(2.0),
+        2.0f32.ln() / 60.0 + 1.0 / 120.0,
+    );
+
+    let exponential = Ramp::new(100.0, 25.0, 2.0, RampCurve::Exponential);
+    assert_float_eq(exponential.value_at(0.0), 100.0);
+    assert_float_eq(exponential.value_at(1.0), 50.0);
+    assert_float_eq(
+        exponential.integrate_reciprocal(2.0) - exponential.integrate_reciprocal(1.0),
+        2.0 * (1.0 / 25.0 - 1.0 / 50.0) / 4.0f32.ln(),
+    );
+    assert!(!exponential.is_over_at(1.0));
+    assert!(exponential.is_over_at(2.0));
+    assert_float_eq(exponential.value_at(3.0), 25.0);
+}
+
+#[test]
+fn test_tempo_ramp() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("T60T~120,1C1").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 4.0 * 2.0f32.ln() - 0.001);
+
+    let tracks = parse(&tokenize("T60T~120,1R2R2T~240,2C4").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    let ramp = 4.0 * 2.0f32.ln();
+    let quarter = 1.5f32.ln();
+    assert_float_eq(generator.track_length(), ramp + quarter - 0.001);
+}
+
+#[test]
+fn test_arpeggio_pitches() {
+    use generate::arpeggio_pitches;
+    use parse::{Arpeggio, ArpeggioDirection::*};
+
+    let arpeggio = |direction, octaves| Arpeggio {
+        speed: 32,
+        direction,
+        octaves,
+    };
+
+    assert_eq!(
+        arpeggio_pitches(&[7, 3, 10], &arpeggio(Up, 1)),
+        vec![3, 7, 10]
+    );
+    assert_eq!(
+        arpeggio_pitches(&[7, 3, 10], &arpeggio(Down, 1)),
+        vec![10, 7, 3]
+    );
+    assert_eq!(
+        arpeggio_pitches(&[3, 7], &arpeggio(UpDown, 2)),
+        vec![3, 7, 15, 19, 15, 7]
+    );
+    assert_eq!(arpeggio_pitches(&[3], &arpeggio(UpDown, 1)), vec![3]);
+}
+
+#[test]
+fn test_arpeggio_length() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("@A32(CEG)4(CEG)4.").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 1.25 - 0.001);
+}
+
+#[test]
+fn test_tuplet_length() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("T60{CDEFG}2{C{DE}4}4C%48").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 4.0 - 0.001);
+
+    let tracks = parse(&tokenize("T60[{CDE}4]300").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 300.0 - 0.001);
+}
+
+#[test]
+fn test_swing_beat() {
+    use generate::swing_beat;
+    use parse::Swing;
+    use ratio::Ratio;
+
+    let swing = Swing {
+        division: 8,
+        ratio: 750,
+    };
+    assert_eq!(swing_beat(Ratio::zero(), &swing), Ratio::zero());
+    assert_eq!(swing_beat(Ratio::new(1, 8), &swing), Ratio::new(3, 16));
+    assert_eq!(swing_beat(Ratio::new(1, 4), &swing), Ratio::new(1, 4));
+    assert_eq!(swing_beat(Ratio::new(5, 16), &swing), Ratio::new(11, 32));
+    assert_eq!(swing_beat(Ratio::new(7, 16), &swing), Ratio::new(15, 32));
+}
+
+#[test]
+fn test_swing_length() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let tracks = parse(&tokenize("T60@S8,750C8").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 0.75 - 0.001);
+
+    let tracks = parse(&tokenize("T60@S8,750C8C8C4.C8@S0C8").unwrap()).unwrap();
+    let generator = Generator::new(44100.0, &tracks);
+    assert_float_eq(generator.track_length(), 3.5 - 0.001);
+}
+
+#[test]
+fn test_humanize() {
+    use generate::Generator;
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let render = |mml: &str| {
+        let tracks = parse(&tokenize(mml).unwrap()).unwrap();
+        Generator::new(44100.0, &tracks).track_length()
+    };
+
+    assert_float_eq(render("T60R4@J100,0,7C4"), render("T60R4@J100,0,7C4"));
+    assert!((render("T60R4@J100,0,7C4") - render("T60R4C4")).abs() > 1e-4);
+    assert!((render("T60R4@J100,0,7C4") - render("T60R4@J100,0,8C4")).abs() > 1e-4);
+}
+
+#[test]
+fn test_drum_voice() {
+    use generate::drums::DrumVoice;
+
+    assert_eq!(DrumVoice::from_pitch(3), (DrumVoice::Kick, 0));
+    assert_eq!(DrumVoice::from_pitch(5), (DrumVoice::Snare, 0));
+    assert_eq!(DrumVoice::from_pitch(7), (DrumVoice::ClosedHiHat, 0));
+    assert_eq!(DrumVoice::from_pitch(14), (DrumVoice::PeriodicNoise, 0));
+    assert_eq!(DrumVoice::from_pitch(15), (DrumVoice::Kick, 12));
+    assert_eq!(DrumVoice::from_pitch(-9), (DrumVoice::Kick, -12));
+}
+
+#[test]
+fn test_drum_render() {
+    use generate::drums::DrumVoice;
+
+    let kick = DrumVoice::Kick.render(1.0, 0, 44100.0);
+    assert_eq!(kick, DrumVoice::Kick.render(1.0, 0, 44100.0));
+    assert_eq!(kick.len(), (DrumVoice::Kick.length() * 44100.0) as usize);
+    assert!(kick.iter().all(|x| x.abs() <= 1.0));
+    assert_ne!(
+        DrumVoice::Snare.render(1.0, 0, 44100.0),
+        DrumVoice::Snare.render(1.0, 1, 44100.0)
+    );
 }
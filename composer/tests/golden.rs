@@ -0,0 +1,102 @@
+//! Renders every `.mml` file in `tests/golden` and compares the result with the `.wav` file of the same name.
+//!
+//! After an intended change of the sound, regenerate the references with
+//! `COMPOSER_BLESS=1 cargo test --test golden` and check the new files in.
+
+use composer::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SAMPLE_RATE: f32 = 11025.0;
+/// Largest difference of a sample allowed, which absorbs floating point differences between platforms
+const TOLERANCE: i32 = 4;
+const HEADER_LENGTH: usize = 44;
+
+fn render(mml: &str) -> Vec<u8> {
+    let tracks = parse::parse(&tokenize::tokenize(mml).unwrap()).unwrap();
+    generate::Generator::new(SAMPLE_RATE, &tracks)
+        .into_riff()
+        .unwrap()
+}
+
+fn samples(riff: &[u8]) -> Vec<i16> {
+    riff[HEADER_LENGTH..]
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect()
+}
+
+fn compare(expected: &[i16], actual: &[i16]) -> Result<(), String> {
+    if expected.len() != actual.len() {
+        return Err(format!(
+            "expected {} samples, but got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+
+    let differences = expected
+        .iter()
+        .zip(actual)
+        .map(|(&a, &b)| (a as i32 - b as i32).abs());
+    match differences
+        .enumerate()
+        .max_by_key(|&(_, difference)| difference)
+    {
+        Some((at, difference)) if difference > TOLERANCE => Err(format!(
+            "sample {} differs by {} (tolerance: {})",
+            at, difference, TOLERANCE
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn fixtures() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut fixtures: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mml"))
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+#[test]
+fn test_golden() {
+    let bless = std::env::var_os("COMPOSER_BLESS").is_some();
+    let fixtures = fixtures();
+    assert!(!fixtures.is_empty());
+
+    let mut failures = Vec::new();
+    for fixture in fixtures {
+        let mml = fs::read_to_string(&fixture).unwrap();
+        let actual = render(mml.trim());
+        let reference = fixture.with_extension("wav");
+
+        if bless {
+            fs::write(&reference, &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read(&reference).unwrap_or_else(|_| {
+            panic!(
+                "{} is missing. Run with COMPOSER_BLESS=1 to create it",
+                reference.display()
+            )
+        });
+        if let Err(message) = compare(&samples(&expected), &samples(&actual)) {
+            failures.push(format!("{}: {}", fixture.display(), message));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_compare() {
+    assert!(compare(&[0, 100, -100], &[0, 100, -100]).is_ok());
+    assert!(compare(&[0, 100, -100], &[TOLERANCE as i16, 100, -100]).is_ok());
+    assert!(compare(&[0, 100, -100], &[0, 100, -100 - TOLERANCE as i16 - 1]).is_err());
+    assert!(compare(&[0, 100], &[0, 100, 0]).is_err());
+}
@@ -5,7 +5,9 @@ fn test_parse() {
     use parse::{parse, Instruction, NoteLength::*, ToneModifier};
     use tokenize::tokenize;
     assert_eq!(
-        parse(&tokenize("T150ab8r4&8..<c4;(cde)4@2[c4d4]2").unwrap()).unwrap(),
+        parse(&tokenize("T150ab8r4&8..<c4;(cde)4@2[c4d4]2").unwrap())
+            .unwrap()
+            .tracks,
         vec![
             vec![
                 Instruction::Tempo(150),
@@ -49,6 +51,11 @@ fn test_parse_length() {
         vec![Length(123), Dot, Dot, Length(45), DefaultLength]
     );
     assert_eq!(single_parse(parse_length, "C"), vec![DefaultLength]);
+    assert_eq!(
+        single_parse(parse_length, "%96.&8"),
+        vec![Ticks(96), Dot, Length(8)]
+    );
+    assert_eq!(single_parse(parse_length, "%C"), vec![DefaultLength]);
 }
 
 #[test]
@@ -112,6 +119,31 @@ fn test_tempo() {
     assert!(single_parse(tempo, "A").unwrap().is_none());
 }
 
+#[test]
+fn test_tempo_ramp() {
+    use parse::tempo::tempo;
+    use parse::{Instruction::TempoRamp, NoteLength::*, RampCurve};
+
+    assert_eq!(
+        single_parse(tempo, "T~180,2."),
+        Ok(Some(TempoRamp(
+            180,
+            vec![Length(2), Dot],
+            RampCurve::Linear
+        )))
+    );
+    assert_eq!(
+        single_parse(tempo, "T^60,1&1"),
+        Ok(Some(TempoRamp(
+            60,
+            vec![Length(1), Length(1)],
+            RampCurve::Exponential
+        )))
+    );
+    assert!(single_parse(tempo, "T~180").is_err());
+    assert!(single_parse(tempo, "T~,4").is_err());
+}
+
 #[test]
 fn test_volume() {
     use parse::volume::volume;
@@ -123,15 +155,133 @@ fn test_volume() {
     assert!(single_parse(volume, "C").unwrap().is_none());
 }
 
+#[test]
+fn test_volume_ramp() {
+    use parse::volume::volume;
+    use parse::{Instruction::VolumeRamp, NoteLength::*, RampCurve};
+
+    assert_eq!(
+        single_parse(volume, "V~50,1"),
+        Ok(Some(VolumeRamp(0.5, vec![Length(1)], RampCurve::Linear)))
+    );
+    assert_eq!(
+        single_parse(volume, "V^200,"),
+        Ok(Some(VolumeRamp(
+            2.0,
+            vec![DefaultLength],
+            RampCurve::Exponential
+        )))
+    );
+    assert!(single_parse(volume, "V~50").is_err());
+}
+
 #[test]
 fn test_tone() {
-    use parse::tone::tone;
+    use parse::tone::{tone, Effect, FilterKind, DEFAULT_FILTER_Q};
     use parse::{
         Instruction::ToneModifier,
-        ToneModifier::{DefinePCMTone, Detune, Envelope, Tone},
+        ToneModifier::{ClearEffects, DefinePCMTone, Detune, Effect as SetEffect, Envelope, Tone},
     };
 
     assert_eq!(single_parse(tone, "@2"), Ok(Some(ToneModifier(Tone(2)))));
+    assert_eq!(
+        single_parse(tone, "@F0"),
+        Ok(Some(ToneModifier(ClearEffects)))
+    );
+    assert!(single_parse(tone, "@F1").is_err());
+    assert_eq!(
+        single_parse(tone, "@FR800,200,300"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Reverb {
+            room_size: 0.8,
+            damping: 0.2,
+            mix: 0.3,
+        }))))
+    );
+    assert!(single_parse(tone, "@FR800,200").is_err());
+    assert_eq!(
+        single_parse(tone, "@FC1500,5,500"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Chorus {
+            rate: 1.5,
+            depth: 0.005,
+            mix: 0.5,
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FF500,2,700,500"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Flanger {
+            rate: 0.5,
+            depth: 0.002,
+            feedback: 0.7,
+            mix: 0.5,
+        }))))
+    );
+    let filter = |kind, cut_off, q, gain| {
+        Ok(Some(ToneModifier(SetEffect(Effect::Filter {
+            kind,
+            cut_off,
+            q,
+            gain,
+        }))))
+    };
+    assert_eq!(
+        single_parse(tone, "@FL500"),
+        filter(FilterKind::LowPass, 500.0, DEFAULT_FILTER_Q, 1.0)
+    );
+    assert_eq!(
+        single_parse(tone, "@FH500,2000"),
+        filter(FilterKind::HighPass, 500.0, 2.0, 1.0)
+    );
+    assert_eq!(
+        single_parse(tone, "@FQ5,1000,700,2000"),
+        filter(FilterKind::Peak, 1000.0, 0.7, 2.0)
+    );
+    assert!(single_parse(tone, "@FQ8,1000").is_err());
+    assert!(single_parse(tone, "@FL500,700,1000").is_err());
+    assert_eq!(
+        single_parse(tone, "@FE200,8000,300,700"),
+        Ok(Some(ToneModifier(SetEffect(Effect::FilterEnvelope {
+            from: 200.0,
+            to: 8000.0,
+            time: 0.3,
+            q: 0.7,
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FM500,4000,10,100"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Compressor {
+            threshold: 0.5,
+            ratio: 4.0,
+            attack: 0.01,
+            release: 0.1,
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FO4000,500"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Distortion {
+            drive: 4.0,
+            mix: 0.5,
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FB4"),
+        Ok(Some(ToneModifier(SetEffect(Effect::BitCrusher {
+            bits: 4
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FS8000"),
+        Ok(Some(ToneModifier(SetEffect(Effect::RateReducer {
+            rate: 8000.0
+        }))))
+    );
+    assert_eq!(
+        single_parse(tone, "@FP300,800,500"),
+        Ok(Some(ToneModifier(SetEffect(Effect::Phaser {
+            rate: 0.3,
+            depth: 0.8,
+            mix: 0.5,
+        }))))
+    );
     assert_eq!(
         single_parse(tone, "@D2,10000"),
         Ok(Some(ToneModifier(Detune(2, 1.0))))
@@ -171,6 +321,202 @@ fn test_chord() {
     assert_eq!(single_parse(chord, "C4"), Ok(None));
 }
 
+#[test]
+fn test_arpeggio() {
+    use parse::arpeggio::arpeggio;
+    use parse::{Arpeggio, ArpeggioDirection, Instruction};
+
+    assert_eq!(
+        single_parse(arpeggio, "@A32"),
+        Ok(Some(Instruction::Arpeggio(Some(Arpeggio {
+            speed: 32,
+            direction: ArpeggioDirection::Up,
+            octaves: 1,
+        }))))
+    );
+    assert_eq!(
+        single_parse(arpeggio, "@A24,2,3"),
+        Ok(Some(Instruction::Arpeggio(Some(Arpeggio {
+            speed: 24,
+            direction: ArpeggioDirection::UpDown,
+            octaves: 3,
+        }))))
+    );
+    assert_eq!(
+        single_parse(arpeggio, "@A0"),
+        Ok(Some(Instruction::Arpeggio(None)))
+    );
+    assert!(single_parse(arpeggio, "@A").is_err());
+    assert!(single_parse(arpeggio, "@A16,3").is_err());
+    assert!(single_parse(arpeggio, "@A16,0,1,2").is_err());
+    // Arpeggios too fast or too wide to render
+    assert!(single_parse(arpeggio, "@A256,2,8").unwrap().is_some());
+    assert!(single_parse(arpeggio, "@A257").is_err());
+    assert!(single_parse(arpeggio, "@A100000000").is_err());
+    assert!(single_parse(arpeggio, "@A1,0,9").is_err());
+    assert!(single_parse(arpeggio, "@A1,0,1000000000").is_err());
+    assert!(single_parse(arpeggio, "@4").unwrap().is_none());
+}
+
+#[test]
+fn test_swing() {
+    use parse::groove::swing;
+    use parse::{Instruction, Swing};
+
+    assert_eq!(
+        single_parse(swing, "@S8,667"),
+        Ok(Some(Instruction::Swing(Some(Swing {
+            division: 8,
+            ratio: 667
+        }))))
+    );
+    assert_eq!(
+        single_parse(swing, "@S0"),
+        Ok(Some(Instruction::Swing(None)))
+    );
+    assert!(single_parse(swing, "@S8").is_err());
+    assert!(single_parse(swing, "@S8,1000").is_err());
+    assert!(single_parse(swing, "@S8,0").is_err());
+    assert!(single_parse(swing, "@T8").unwrap().is_none());
+}
+
+#[test]
+fn test_humanize() {
+    use parse::groove::humanize;
+    use parse::Instruction::Humanize;
+
+    assert_eq!(
+        single_parse(humanize, "@J10,20"),
+        Ok(Some(Humanize(0.01, 0.2, None)))
+    );
+    assert_eq!(
+        single_parse(humanize, "@J10,20,42"),
+        Ok(Some(Humanize(0.01, 0.2, Some(42))))
+    );
+    assert!(single_parse(humanize, "@J10").is_err());
+    assert!(single_parse(humanize, "@J1,2,3,4").is_err());
+}
+
+#[test]
+fn test_drum_kit() {
+    use parse::drum::drum_kit;
+    use parse::Instruction::DrumKit;
+
+    assert_eq!(single_parse(drum_kit, "@K1"), Ok(Some(DrumKit(true))));
+    assert_eq!(single_parse(drum_kit, "@K0"), Ok(Some(DrumKit(false))));
+    assert!(single_parse(drum_kit, "@K2").is_err());
+}
+
+#[test]
+fn test_seed() {
+    use parse::seed::seed;
+    use parse::Instruction::Seed;
+
+    assert_eq!(single_parse(seed, "@R42"), Ok(Some(Seed(42))));
+    assert!(single_parse(seed, "@R").is_err());
+}
+
+#[test]
+fn test_metadata() {
+    use parse::metadata::metadata;
+    use parse::{parse, Instruction, Metadata, MetadataField, NoteLength::*, ParseError};
+    use tokenize::{tokenize, TokenKind};
+
+    assert_eq!(
+        single_parse(metadata, "#TITLE \"Song 1\""),
+        Ok(Some(Instruction::Metadata(MetadataField::Title(
+            "Song 1".to_string()
+        ))))
+    );
+    assert_eq!(
+        single_parse(metadata, "#Tempo150"),
+        Ok(Some(Instruction::Metadata(MetadataField::Tempo(150))))
+    );
+    assert_eq!(single_parse(metadata, "@T150"), Ok(None));
+    assert_eq!(
+        single_parse(metadata, "#ARTIST\"me\""),
+        Err(ParseError::UnexpectedToken((2, TokenKind::Character('a'))))
+    );
+    assert!(single_parse(metadata, "#TITLE{Song}").is_err());
+
+    let parsed = parse(
+        &tokenize("#TITLE\"Song\" #COMPOSER\"Me\" #TEMPO90 #TRACK\"Lead\"C;D;#TRACK\"Bass\"E")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        parsed.metadata,
+        Metadata {
+            title: Some("Song".to_string()),
+            composer: Some("Me".to_string()),
+            tempo: Some(90),
+        }
+    );
+    assert_eq!(
+        parsed.tracks[0],
+        vec![Instruction::Note(3, vec![DefaultLength])]
+    );
+    assert_eq!(parsed.track_index("Bass"), Some(2));
+    assert_eq!(parsed.track_index("Drums"), None);
+    assert_eq!(parsed.track_name(0), "Lead");
+    assert_eq!(parsed.track_name(1), "1");
+
+    // A header in its own section does not make a silent track
+    let parsed =
+        parse(&tokenize("#TITLE\"Song\" #TEMPO90;CDE;;#TRACK\"Bass\";E").unwrap()).unwrap();
+    assert_eq!(parsed.metadata.title, Some("Song".to_string()));
+    assert_eq!(parsed.tracks.len(), 3);
+    assert_eq!(parsed.tracks[0].len(), 3);
+    assert!(parsed.tracks[1].is_empty());
+    assert_eq!(
+        parsed.track_names,
+        vec![None, None, Some("Bass".to_string())]
+    );
+
+    // Metadata belongs to the song or the track, so it cannot be repeated
+    assert!(parse(&tokenize("[#TEMPO90C]2").unwrap()).is_err());
+}
+
+#[test]
+fn test_bus() {
+    use parse::bus::{bus, send};
+    use parse::tone::Effect;
+    use parse::Instruction::{Bus, Send};
+
+    let delay = Effect::Delay {
+        delay: 0.1,
+        feedback: 0.5,
+    };
+    assert_eq!(
+        single_parse(bus, "@B{Room}(@FD100,500)"),
+        Ok(Some(Bus("Room".to_string(), vec![delay])))
+    );
+    assert_eq!(
+        single_parse(bus, "@B{room}()"),
+        Ok(Some(Bus("room".to_string(), vec![])))
+    );
+    assert!(single_parse(bus, "@B{room}(@3)").is_err());
+    assert!(single_parse(bus, "@B{room}(@FD100,500").is_err());
+    assert_eq!(
+        single_parse(send, "@X{room}30"),
+        Ok(Some(Send("room".to_string(), 0.3)))
+    );
+    assert!(single_parse(send, "@X30").is_err());
+}
+
+#[test]
+fn test_marker() {
+    use parse::marker::marker;
+    use parse::Instruction::Marker;
+
+    assert_eq!(
+        single_parse(marker, "@M{Chorus}"),
+        Ok(Some(Marker("Chorus".to_string())))
+    );
+    assert!(single_parse(marker, "@M").is_err());
+    assert!(single_parse(marker, "@M1").is_err());
+}
+
 #[test]
 fn test_repeat() {
     use parse::repeat::repeat;
@@ -197,6 +543,84 @@ fn test_repeat() {
     assert!(single_parse(repeat, "94").unwrap().is_none());
 }
 
+#[test]
+fn test_nesting_limit() {
+    use parse::{parse, ParseError, MAX_NESTING};
+    use tokenize::tokenize;
+
+    let nested = |open: &str, close: &str, depth: usize| {
+        let mml = format!("{}C{}", open.repeat(depth), close.repeat(depth));
+        parse(&tokenize(&mml).unwrap())
+    };
+
+    assert!(nested("[", "]1", MAX_NESTING).is_ok());
+    assert_eq!(
+        nested("[", "]1", MAX_NESTING + 1),
+        Err(ParseError::TooDeeplyNested(MAX_NESTING + 1))
+    );
+    assert!(nested("[", "]1", 100000).is_err());
+    assert!(nested("{", "}4", MAX_NESTING).is_ok());
+    assert!(nested("{", "}4", MAX_NESTING + 1).is_err());
+    assert!(nested("[{", "}4]2", MAX_NESTING).is_err());
+}
+
+#[test]
+fn test_expansion_limit() {
+    use parse::{parse, ParseError, MAX_EXPANDED};
+    use tokenize::tokenize;
+
+    let parsed = |mml: &str| parse(&tokenize(mml).unwrap());
+
+    assert!(parsed(&format!("[C]{}", MAX_EXPANDED)).is_ok());
+    assert!(parsed(&format!("[{{C}}4]{}", MAX_EXPANDED)).is_ok());
+    assert_eq!(
+        parsed(&format!("[C]{}", MAX_EXPANDED + 1)),
+        Err(ParseError::TooLong(1))
+    );
+    assert_eq!(
+        parsed(&format!("CD;[C]{}", MAX_EXPANDED - 1)),
+        Err(ParseError::TooLong(4))
+    );
+    assert_eq!(parsed("C[[[[C]99]99]99]99"), Err(ParseError::TooLong(2)));
+    assert!(parsed(&format!("{}C{}", "[".repeat(32), "]2".repeat(32))).is_err());
+}
+
+#[test]
+fn test_error_position() {
+    use parse::parse;
+    use tokenize::tokenize;
+
+    let position = |mml: &str| parse(&tokenize(mml).unwrap()).unwrap_err().position();
+    assert_eq!(position("CD\nE!"), Some(5));
+    assert_eq!(position("C@E1,2"), Some(2));
+    assert_eq!(position("[CDE"), None);
+}
+
+#[test]
+fn test_tuplet() {
+    use parse::tuplet::tuplet;
+    use parse::{
+        Instruction::{Note, Rest, Tuplet},
+        NoteLength::*,
+    };
+
+    assert_eq!(
+        single_parse(tuplet, "{C D8.R}4."),
+        Ok(Some(Tuplet(
+            vec![
+                Note(3, vec![DefaultLength]),
+                Note(5, vec![Length(8), Dot]),
+                Rest(vec![DefaultLength])
+            ],
+            vec![Length(4), Dot]
+        )))
+    );
+    assert!(single_parse(tuplet, "{CD;E}4").is_err());
+    assert!(single_parse(tuplet, "{CD]E}4").is_err());
+    assert!(single_parse(tuplet, "{CH}4").is_err());
+    assert!(single_parse(tuplet, "C4").unwrap().is_none());
+}
+
 #[test]
 fn test_synthesize() {
     use parse::tone::synthesize;
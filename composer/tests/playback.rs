@@ -0,0 +1,176 @@
+use composer::*;
+use playback::{lock, prepare_loop, Player, Region, Song, Watcher};
+use std::sync::Mutex;
+
+const SAMPLE_RATE: u32 = 8000;
+
+fn song(mml: &str, region: Region) -> Song {
+    Song {
+        mml: parse::parse(&tokenize::tokenize(mml).unwrap()).unwrap(),
+        muted: Vec::new(),
+        soloed: Vec::new(),
+        region,
+    }
+}
+
+/// Samples of the song from the position, as the player plays them after starting there
+fn samples(song: &Song, position: usize, count: usize) -> Vec<f32> {
+    song.generator_at(SAMPLE_RATE, position)
+        .take(count)
+        .collect()
+}
+
+fn play(player: &Mutex<Player>, count: usize) -> Vec<f32> {
+    let mut player = lock(player);
+    (0..count).map(|_| player.next_sample()).collect()
+}
+
+#[test]
+fn test_region() {
+    let region = Region {
+        start: 10,
+        end: Some(20),
+        looping: false,
+    };
+    assert!(!region.contains(9));
+    assert!(region.contains(10));
+    assert!(region.contains(19));
+    assert!(!region.contains(20));
+    assert!(Region::default().contains(usize::MAX));
+}
+
+#[test]
+fn test_player() {
+    let whole = song("T240C", Region::default());
+    let expected: Vec<_> = whole.generator_at(SAMPLE_RATE, 0).collect();
+    let mut player = Player::default();
+    player.start(whole.generator_at(SAMPLE_RATE, 0), 0, whole.region);
+    let mut played = Vec::new();
+    while !player.is_over() {
+        played.push(player.next_sample());
+    }
+    // The silence which ends the song
+    assert_eq!(played.pop(), Some(0.0));
+    assert_eq!(played, expected);
+    assert_eq!(player.position(), expected.len());
+    assert_eq!(player.next_sample(), 0.0);
+
+    let region = Region {
+        start: 500,
+        end: Some(1000),
+        looping: false,
+    };
+    let part = song("T240C", region);
+    player.start(part.generator_at(SAMPLE_RATE, 500), 500, region);
+    assert_eq!(
+        (0..500).map(|_| player.next_sample()).collect::<Vec<_>>(),
+        samples(&part, 500, 500)
+    );
+    assert!(!player.is_over());
+    assert_eq!(player.next_sample(), 0.0);
+    assert!(player.is_over());
+    assert_eq!(player.position(), 1000);
+}
+
+#[test]
+fn test_player_loop() {
+    let region = Region {
+        start: 500,
+        end: Some(1000),
+        looping: true,
+    };
+    let song = song("T240C", region);
+    let player = Mutex::new(Player::default());
+    lock(&player).start(song.generator_at(SAMPLE_RATE, 500), 500, region);
+    assert!(lock(&player).needs_next());
+    assert_eq!(play(&player, 500), samples(&song, 500, 500));
+
+    // Silence is played until the next loop is ready
+    assert_eq!(play(&player, 10), vec![0.0; 10]);
+    assert!(!lock(&player).is_over());
+    assert_eq!(lock(&player).position(), 1000);
+
+    prepare_loop(&player, &song, SAMPLE_RATE);
+    assert!(!lock(&player).needs_next());
+    assert_eq!(play(&player, 100), samples(&song, 500, 100));
+    assert_eq!(lock(&player).position(), 600);
+    assert!(lock(&player).needs_next());
+}
+
+#[test]
+fn test_watcher() {
+    let first = || song("T240C", Region::default());
+    let second = || song("T240D", Region::default());
+    let player = Mutex::new(Player::default());
+    let mut watcher = Watcher::new(SAMPLE_RATE, Some(first()));
+    assert_eq!(watcher.update(&player), Some(0));
+    assert_eq!(watcher.update(&player), None);
+    assert_eq!(play(&player, 1000), samples(&first(), 0, 1000));
+
+    // A broken song leaves the one playing as it was
+    let result = watcher.reload(&lock(&player), Err("broken".to_string()));
+    assert_eq!(result, Err("broken".to_string()));
+    assert_eq!(watcher.update(&player), None);
+    assert_eq!(watcher.song().unwrap().mml, first().mml);
+    assert_eq!(play(&player, 1000), samples(&first(), 0, 2000)[1000..]);
+
+    // The changed song goes on from where the player is
+    watcher.reload(&lock(&player), Ok(second())).unwrap();
+    assert_eq!(watcher.update(&player), Some(2000));
+    assert_eq!(watcher.song().unwrap().mml, second().mml);
+    assert_eq!(play(&player, 100), samples(&second(), 2000, 100));
+
+    // The mark takes over from where the player is until it is removed
+    assert_eq!(watcher.set_mark(&lock(&player)), 2100);
+    assert_eq!(watcher.mark(), Some(2100));
+    play(&player, 300);
+    watcher.reload(&lock(&player), Ok(first())).unwrap();
+    assert_eq!(watcher.update(&player), Some(2100));
+    assert_eq!(play(&player, 100), samples(&first(), 2100, 100));
+    watcher.restart();
+    assert_eq!(watcher.update(&player), Some(2100));
+    watcher.remove_mark();
+    watcher.restart();
+    assert_eq!(watcher.update(&player), Some(0));
+
+    // A song which has ended starts over
+    while !lock(&player).is_over() {
+        play(&player, 1000);
+    }
+    watcher.reload(&lock(&player), Ok(second())).unwrap();
+    assert_eq!(watcher.update(&player), Some(0));
+
+    // So does a song whose region has moved away from the player
+    play(&player, 100);
+    let region = Region {
+        start: 1500,
+        end: None,
+        looping: false,
+    };
+    watcher
+        .reload(&lock(&player), Ok(song("T240C", region)))
+        .unwrap();
+    assert_eq!(watcher.update(&player), Some(1500));
+    assert_eq!(lock(&player).position(), 1500);
+}
+
+#[test]
+fn test_watcher_without_song() {
+    let player = Mutex::new(Player::default());
+    let mut watcher = Watcher::new(SAMPLE_RATE, None);
+    assert_eq!(watcher.update(&player), None);
+    assert_eq!(play(&player, 10), vec![0.0; 10]);
+
+    let region = Region {
+        start: 500,
+        end: None,
+        looping: true,
+    };
+    watcher
+        .reload(&lock(&player), Ok(song("T240C", region)))
+        .unwrap();
+    assert_eq!(watcher.update(&player), Some(500));
+    assert_eq!(lock(&player).position(), 500);
+    // The next loop is prepared along with the start
+    assert!(!lock(&player).needs_next());
+}
@@ -0,0 +1,32 @@
+use composer::*;
+
+#[test]
+fn test_ratio() {
+    use ratio::Ratio;
+
+    assert_eq!(Ratio::new(2, 8), Ratio::new(1, 4));
+    assert_eq!(Ratio::new(1, 0), Ratio::zero());
+    assert_eq!(Ratio::new(1, 6) + Ratio::new(1, 3), Ratio::new(1, 2));
+    assert_eq!(Ratio::new(1, 2) - Ratio::new(1, 3), Ratio::new(1, 6));
+    assert_eq!(Ratio::new(2, 3) * Ratio::new(3, 4), Ratio::new(1, 2));
+    assert_eq!(Ratio::new(1, 4) / Ratio::new(3, 4), Ratio::new(1, 3));
+    assert_eq!(Ratio::new(1, 4) / Ratio::zero(), Ratio::zero());
+    assert_eq!(
+        Ratio::new(1, 3).saturating_sub(Ratio::new(1, 2)),
+        Ratio::zero()
+    );
+    assert!(Ratio::new(1, 3) < Ratio::new(1, 2));
+    assert_eq!(Ratio::new(3, 4).to_f32(), 0.75);
+
+    let sum = (0..36).fold(Ratio::zero(), |sum, _| sum + Ratio::new(1, 12));
+    assert_eq!(sum, Ratio::from_integer(3));
+}
+
+#[test]
+fn test_ratio_overflow() {
+    use ratio::Ratio;
+
+    let huge = Ratio::new(1, u64::MAX - 1);
+    let approximated = huge + Ratio::new(1, u64::MAX - 2);
+    assert!((approximated.to_f64() - 2.0 / u64::MAX as f64).abs() < 1e-9);
+}
@@ -6,6 +6,7 @@ fn token_test() {
     assert!(TokenKind::Character('c').is_character());
     assert!(TokenKind::Number(42).is_number());
     assert!(TokenKind::BraceString("string".to_string()).is_brace_string());
+    assert!(TokenKind::QuotedString("string".to_string()).is_quoted_string());
 }
 
 #[test]
@@ -13,6 +14,15 @@ fn tokenize_test() {
     use tokenize::*;
     use TokenKind::*;
     assert!(tokenize("Do some 焼き松茸").is_err());
+    assert!(tokenize("\"unterminated").is_err());
+    assert_eq!(
+        tokenize("C\"焼き 松茸\"4"),
+        Ok(vec![
+            (1, Character('c')),
+            (2, QuotedString("焼き 松茸".to_string())),
+            (9, Number(4)),
+        ])
+    );
     assert!(tokenize("9999999999999999999999999999999999999999999999999").is_err());
     assert_eq!(
         tokenize("c256e16g4<CEG4{This Is String}"),
@@ -31,6 +41,10 @@ fn tokenize_test() {
             (15, BraceString("ThisIsString".to_string()))
         ])
     );
+    assert_eq!(
+        tokenize("{C{D E}}4"),
+        Ok(vec![(1, BraceString("C{DE}".to_string())), (9, Number(4)),])
+    );
     assert_eq!(
         tokenize("C e\n\rG"),
         Ok(vec![
@@ -1,8 +1,14 @@
+use std::fmt;
+use std::io::{self, Write};
 use std::ops::Drop;
-use std::os::raw::{c_int, c_void};
+use std::os::raw::{c_char, c_float, c_int, c_ushort, c_void};
 
 pub type LamePtr = *mut c_void;
 
+const VBR_OFF: c_int = 0;
+const VBR_ABR: c_int = 3;
+const VBR_MTRH: c_int = 4;
+
 #[link(name = "mp3lame")]
 extern "C" {
     pub fn lame_init() -> LamePtr;
@@ -17,6 +23,10 @@ extern "C" {
     pub fn lame_get_quality(ptr: LamePtr) -> c_int;
     pub fn lame_set_brate(ptr: LamePtr, brate: c_int) -> c_int;
     pub fn lame_get_brate(ptr: LamePtr) -> c_int;
+    pub fn lame_set_VBR(ptr: LamePtr, vbr_mode: c_int) -> c_int;
+    pub fn lame_set_VBR_quality(ptr: LamePtr, quality: c_float) -> c_int;
+    pub fn lame_set_VBR_mean_bitrate_kbps(ptr: LamePtr, kbps: c_int) -> c_int;
+    pub fn lame_set_write_id3tag_automatic(ptr: LamePtr, automatic: c_int);
     pub fn lame_init_params(ptr: LamePtr) -> c_int;
     pub fn lame_encode_buffer(
         ptr: LamePtr,
@@ -26,18 +36,85 @@ extern "C" {
         mp3buffer: *mut u8,
         mp3buffer_size: c_int,
     ) -> c_int;
+    pub fn lame_encode_buffer_interleaved(
+        ptr: LamePtr,
+        pcm: *mut i16,
+        num_samples: c_int,
+        mp3buffer: *mut u8,
+        mp3buffer_size: c_int,
+    ) -> c_int;
+    pub fn lame_encode_buffer_ieee_float(
+        ptr: LamePtr,
+        leftpcm: *const c_float,
+        rightpcm: *const c_float,
+        num_samples: c_int,
+        mp3buffer: *mut u8,
+        mp3buffer_size: c_int,
+    ) -> c_int;
+    pub fn lame_encode_buffer_interleaved_ieee_float(
+        ptr: LamePtr,
+        pcm: *const c_float,
+        num_samples: c_int,
+        mp3buffer: *mut u8,
+        mp3buffer_size: c_int,
+    ) -> c_int;
+    pub fn lame_encode_flush(ptr: LamePtr, mp3buffer: *mut u8, size: c_int) -> c_int;
+    pub fn id3tag_init(ptr: LamePtr);
+    pub fn id3tag_v2_only(ptr: LamePtr);
+    pub fn id3tag_set_textinfo_utf16(
+        ptr: LamePtr,
+        id: *const c_char,
+        text: *const c_ushort,
+    ) -> c_int;
+    pub fn lame_get_id3v2_tag(ptr: LamePtr, buffer: *mut u8, size: usize) -> usize;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     GenericError,
     NoMem,
     BadBitRate,
     BadSampleFreq,
     InternalError,
+    /// `lame_init` returned null
+    InitFailed,
+    /// The parameters cannot be changed once encoding has begun
+    AlreadyInitialized,
+    /// The channels have different numbers of samples, or interleaved samples are not whole frames
+    ChannelMismatch,
+    BufferTooSmall,
+    PsychoAcoustic,
+    InvalidTag,
     Unknown(c_int),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::GenericError => write!(f, "LAME failed"),
+            Error::NoMem => write!(f, "LAME ran out of memory"),
+            Error::BadBitRate => write!(f, "unsupported bit rate"),
+            Error::BadSampleFreq => write!(f, "unsupported sample rate"),
+            Error::InternalError => write!(f, "internal error of LAME"),
+            Error::InitFailed => write!(f, "failed to initialize LAME"),
+            Error::AlreadyInitialized => write!(f, "parameters are set after encoding began"),
+            Error::ChannelMismatch => write!(f, "samples do not match the channels"),
+            Error::BufferTooSmall => write!(f, "the MP3 buffer is too small"),
+            Error::PsychoAcoustic => write!(f, "psychoacoustic analysis failed"),
+            Error::InvalidTag => write!(f, "invalid ID3 tag"),
+            Error::Unknown(err) => write!(f, "LAME returned {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::other(err)
+    }
+}
+
 fn int_to_result(ret: c_int) -> Result<(), Error> {
     match ret {
         0 => Ok(()),
@@ -50,8 +127,60 @@ fn int_to_result(ret: c_int) -> Result<(), Error> {
     }
 }
 
+/// Bytes written by the `lame_encode_*` functions, which have their own error codes
+fn encoded_size(ret: c_int) -> Result<usize, Error> {
+    match ret {
+        size if size >= 0 => Ok(size as usize),
+        -1 => Err(Error::BufferTooSmall),
+        -2 => Err(Error::NoMem),
+        -3 => Err(Error::InternalError),
+        -4 => Err(Error::PsychoAcoustic),
+        err => Err(Error::Unknown(err)),
+    }
+}
+
+/// The worst case size of the MP3 data for the samples of a channel as lame.h documents,
+/// which is also enough for `lame_encode_flush`
+fn buffer_size(samples: usize) -> usize {
+    samples * 5 / 4 + 7200
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bitrate {
+    /// Kilobits per second
+    Constant(i32),
+    /// Quality from 0 (best) to 9.999 (smallest)
+    Variable(f32),
+    /// Kilobits per second on average
+    Average(i32),
+}
+
+/// ID3v2 text frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Title,
+    Artist,
+    Album,
+    Year,
+}
+
+impl Tag {
+    fn id(self) -> &'static [u8; 5] {
+        match self {
+            Tag::Title => b"TIT2\0",
+            Tag::Artist => b"TPE1\0",
+            Tag::Album => b"TALB\0",
+            Tag::Year => b"TYER\0",
+        }
+    }
+}
+
+/// An encoder whose parameters are fixed by the first call of `init_params` or of an `encode_*` method
 pub struct Lame {
     ptr: LamePtr,
+    initialized: bool,
+    /// The ID3v2 tag goes before the first frame
+    tag_written: bool,
 }
 
 impl Lame {
@@ -59,11 +188,27 @@ impl Lame {
         let ctx = unsafe { lame_init() };
 
         if ctx.is_null() {
-            panic!("lame_init() returned null");
+            return Err(Error::InitFailed);
+        }
+
+        unsafe {
+            id3tag_init(ctx);
+            // The tag is returned with the first frames instead of being written into the buffer
+            lame_set_write_id3tag_automatic(ctx, 0);
         }
+        Ok(Lame {
+            ptr: ctx,
+            initialized: false,
+            tag_written: false,
+        })
+    }
 
-        int_to_result(unsafe { lame_init_params(ctx) })?;
-        Ok(Lame { ptr: ctx })
+    /// Calls a setter of LAME unless the parameters are already fixed
+    fn set(&mut self, setter: impl FnOnce(LamePtr) -> c_int) -> Result<(), Error> {
+        if self.initialized {
+            return Err(Error::AlreadyInitialized);
+        }
+        int_to_result(setter(self.ptr))
     }
 
     pub fn samplerate_in(&self) -> i32 {
@@ -71,7 +216,7 @@ impl Lame {
     }
 
     pub fn set_samplerate_in(&mut self, sample_rate: i32) -> Result<(), Error> {
-        int_to_result(unsafe { lame_set_in_samplerate(self.ptr, sample_rate as c_int) })
+        self.set(|ptr| unsafe { lame_set_in_samplerate(ptr, sample_rate as c_int) })
     }
 
     pub fn samplerate_out(&self) -> i32 {
@@ -79,7 +224,7 @@ impl Lame {
     }
 
     pub fn set_samplerate_out(&mut self, sample_rate: i32) -> Result<(), Error> {
-        int_to_result(unsafe { lame_set_out_samplerate(self.ptr, sample_rate as c_int) })
+        self.set(|ptr| unsafe { lame_set_out_samplerate(ptr, sample_rate as c_int) })
     }
 
     pub fn set_samplerate(&mut self, sample_rate: i32) -> Result<(), Error> {
@@ -92,7 +237,7 @@ impl Lame {
     }
 
     pub fn set_channels(&mut self, channels: u8) -> Result<(), Error> {
-        int_to_result(unsafe { lame_set_num_channels(self.ptr, channels as c_int) })
+        self.set(|ptr| unsafe { lame_set_num_channels(ptr, channels as c_int) })
     }
 
     pub fn quality(&self) -> i32 {
@@ -100,52 +245,159 @@ impl Lame {
     }
 
     pub fn set_quality(&mut self, quality: i32) -> Result<(), Error> {
-        int_to_result(unsafe { lame_set_quality(self.ptr, quality as c_int) })
+        self.set(|ptr| unsafe { lame_set_quality(ptr, quality as c_int) })
     }
 
     pub fn kilobitrate(&self) -> i32 {
         unsafe { lame_get_brate(self.ptr) as i32 }
     }
 
-    pub fn set_kilobitrate(&mut self, quality: i32) -> Result<(), Error> {
-        int_to_result(unsafe { lame_set_brate(self.ptr, quality as c_int) })
+    /// Constant bitrate, the same as `set_bitrate(Bitrate::Constant(kilobitrate))`
+    pub fn set_kilobitrate(&mut self, kilobitrate: i32) -> Result<(), Error> {
+        self.set_bitrate(Bitrate::Constant(kilobitrate))
     }
 
-    pub fn encode(&mut self, pcm_left: &[i16], pcm_right: &[i16]) -> Result<Vec<u8>, i32> {
-        if pcm_left.len() != pcm_right.len() {
-            panic!(
-                "The number of left and right channel must be same\n  left: {}\n right: {}",
-                pcm_left.len(),
-                pcm_right.len()
-            );
+    pub fn set_bitrate(&mut self, bitrate: Bitrate) -> Result<(), Error> {
+        match bitrate {
+            Bitrate::Constant(kilobitrate) => {
+                self.set(|ptr| unsafe { lame_set_VBR(ptr, VBR_OFF) })?;
+                self.set(|ptr| unsafe { lame_set_brate(ptr, kilobitrate as c_int) })
+            }
+            Bitrate::Variable(quality) => {
+                self.set(|ptr| unsafe { lame_set_VBR(ptr, VBR_MTRH) })?;
+                self.set(|ptr| unsafe { lame_set_VBR_quality(ptr, quality as c_float) })
+            }
+            Bitrate::Average(kilobitrate) => {
+                self.set(|ptr| unsafe { lame_set_VBR(ptr, VBR_ABR) })?;
+                self.set(|ptr| unsafe { lame_set_VBR_mean_bitrate_kbps(ptr, kilobitrate) })
+            }
+        }
+    }
+
+    /// Adds a text frame to the ID3v2 tag at the beginning of the output
+    pub fn set_tag(&mut self, tag: Tag, text: &str) -> Result<(), Error> {
+        // LAME takes UTF-16 with a byte order mark, terminated with null
+        let text: Vec<u16> = std::iter::once(0xfeff)
+            .chain(text.encode_utf16())
+            .chain(std::iter::once(0))
+            .collect();
+        let id = tag.id().as_ptr() as *const c_char;
+        if self.initialized {
+            return Err(Error::AlreadyInitialized);
         }
+        match unsafe {
+            id3tag_v2_only(self.ptr);
+            id3tag_set_textinfo_utf16(self.ptr, id, text.as_ptr())
+        } {
+            0 => Ok(()),
+            _ => Err(Error::InvalidTag),
+        }
+    }
+
+    /// Fixes the parameters. The encoding methods call this if it has not been called.
+    pub fn init_params(&mut self) -> Result<(), Error> {
+        if !self.initialized {
+            int_to_result(unsafe { lame_init_params(self.ptr) })?;
+            self.initialized = true;
+        }
+        Ok(())
+    }
+
+    /// Runs an encoding function with a buffer large enough for the samples of a channel
+    fn encode_with(
+        &mut self,
+        samples: usize,
+        encode: impl FnOnce(LamePtr, *mut u8, c_int) -> c_int,
+    ) -> Result<Vec<u8>, Error> {
+        self.init_params()?;
 
         let mut mp3_buffer = Vec::new();
-        mp3_buffer.resize(pcm_left.len(), 0);
-
-        let ret = unsafe {
-            lame_encode_buffer(
-                self.ptr,
-                pcm_left.as_ptr(),
-                pcm_right.as_ptr(),
-                pcm_left.len() as c_int,
-                mp3_buffer.as_mut_ptr(),
-                mp3_buffer.len() as c_int,
-            )
-        };
-
-        match ret {
-            size if size >= 0 => {
-                mp3_buffer.resize(size as usize, 0);
-                Ok(mp3_buffer)
-            }
-            err => Err(err as i32),
+        if !self.tag_written {
+            let size = unsafe { lame_get_id3v2_tag(self.ptr, std::ptr::null_mut(), 0) };
+            mp3_buffer.resize(size, 0);
+            unsafe { lame_get_id3v2_tag(self.ptr, mp3_buffer.as_mut_ptr(), size) };
+            self.tag_written = true;
+        }
+
+        let start = mp3_buffer.len();
+        mp3_buffer.resize(start + buffer_size(samples), 0);
+        let available = (mp3_buffer.len() - start) as c_int;
+        let size = encoded_size(encode(
+            self.ptr,
+            mp3_buffer[start..].as_mut_ptr(),
+            available,
+        ))?;
+        mp3_buffer.truncate(start + size);
+        Ok(mp3_buffer)
+    }
+
+    pub fn encode(&mut self, pcm_left: &[i16], pcm_right: &[i16]) -> Result<Vec<u8>, Error> {
+        if pcm_left.len() != pcm_right.len() {
+            return Err(Error::ChannelMismatch);
         }
+
+        let samples = pcm_left.len();
+        self.encode_with(samples, |ptr, buffer, size| unsafe {
+            let (left, right) = (pcm_left.as_ptr(), pcm_right.as_ptr());
+            lame_encode_buffer(ptr, left, right, samples as c_int, buffer, size)
+        })
     }
 
-    pub fn encode_mono(&mut self, pcm: &[i16]) -> Result<Vec<u8>, i32> {
+    pub fn encode_mono(&mut self, pcm: &[i16]) -> Result<Vec<u8>, Error> {
         self.encode(pcm, pcm)
     }
+
+    /// Encodes samples of all the channels in turn
+    pub fn encode_interleaved(&mut self, pcm: &[i16]) -> Result<Vec<u8>, Error> {
+        let samples = self.frames(pcm.len())?;
+        self.encode_with(samples, |ptr, buffer, size| unsafe {
+            // LAME does not modify the samples even though it takes them as mutable
+            let pcm = pcm.as_ptr() as *mut i16;
+            lame_encode_buffer_interleaved(ptr, pcm, samples as c_int, buffer, size)
+        })
+    }
+
+    /// Encodes samples in [-1, 1]
+    pub fn encode_float(&mut self, pcm_left: &[f32], pcm_right: &[f32]) -> Result<Vec<u8>, Error> {
+        if pcm_left.len() != pcm_right.len() {
+            return Err(Error::ChannelMismatch);
+        }
+
+        let samples = pcm_left.len();
+        self.encode_with(samples, |ptr, buffer, size| unsafe {
+            let (left, right) = (pcm_left.as_ptr(), pcm_right.as_ptr());
+            lame_encode_buffer_ieee_float(ptr, left, right, samples as c_int, buffer, size)
+        })
+    }
+
+    pub fn encode_float_mono(&mut self, pcm: &[f32]) -> Result<Vec<u8>, Error> {
+        self.encode_float(pcm, pcm)
+    }
+
+    pub fn encode_float_interleaved(&mut self, pcm: &[f32]) -> Result<Vec<u8>, Error> {
+        let samples = self.frames(pcm.len())?;
+        self.encode_with(samples, |ptr, buffer, size| unsafe {
+            let pcm = pcm.as_ptr();
+            lame_encode_buffer_interleaved_ieee_float(ptr, pcm, samples as c_int, buffer, size)
+        })
+    }
+
+    /// Samples per channel of interleaved samples
+    fn frames(&self, samples: usize) -> Result<usize, Error> {
+        let channels = self.channels().max(1) as usize;
+        let frames = samples / channels;
+        if frames * channels != samples {
+            return Err(Error::ChannelMismatch);
+        }
+        Ok(frames)
+    }
+
+    /// Encodes the samples left in LAME, which are needed to end the file
+    pub fn flush(&mut self) -> Result<Vec<u8>, Error> {
+        self.encode_with(0, |ptr, buffer, size| unsafe {
+            lame_encode_flush(ptr, buffer, size)
+        })
+    }
 }
 
 impl Drop for Lame {
@@ -153,3 +405,114 @@ impl Drop for Lame {
         unsafe { lame_close(self.ptr) };
     }
 }
+
+/// Writes the MP3 data to the writer as the samples are encoded
+pub struct Mp3Writer<W: Write> {
+    lame: Lame,
+    writer: W,
+}
+
+impl<W: Write> Mp3Writer<W> {
+    pub fn new(lame: Lame, writer: W) -> Self {
+        Mp3Writer { lame, writer }
+    }
+
+    fn write(&mut self, mp3: Result<Vec<u8>, Error>) -> io::Result<()> {
+        self.writer.write_all(&mp3?)
+    }
+
+    pub fn write_mono(&mut self, pcm: &[i16]) -> io::Result<()> {
+        let mp3 = self.lame.encode_mono(pcm);
+        self.write(mp3)
+    }
+
+    pub fn write_interleaved(&mut self, pcm: &[i16]) -> io::Result<()> {
+        let mp3 = self.lame.encode_interleaved(pcm);
+        self.write(mp3)
+    }
+
+    pub fn write_float_mono(&mut self, pcm: &[f32]) -> io::Result<()> {
+        let mp3 = self.lame.encode_float_mono(pcm);
+        self.write(mp3)
+    }
+
+    pub fn write_float_interleaved(&mut self, pcm: &[f32]) -> io::Result<()> {
+        let mp3 = self.lame.encode_float_interleaved(pcm);
+        self.write(mp3)
+    }
+
+    /// Flushes LAME and returns the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let mp3 = self.lame.flush();
+        self.write(mp3)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An encoder which has begun encoding, without calling LAME. It has nothing to close, so it
+    /// is forgotten instead of dropped
+    fn initialized() -> Lame {
+        Lame {
+            ptr: std::ptr::null_mut(),
+            initialized: true,
+            tag_written: false,
+        }
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        assert_eq!(buffer_size(0), 7200);
+        assert_eq!(buffer_size(1152), 1440 + 7200);
+        assert_eq!(buffer_size(4096), 5120 + 7200);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(int_to_result(0), Ok(()));
+        assert_eq!(int_to_result(-11), Err(Error::BadBitRate));
+        assert_eq!(int_to_result(-99), Err(Error::Unknown(-99)));
+        assert_eq!(encoded_size(417), Ok(417));
+        assert_eq!(encoded_size(-1), Err(Error::BufferTooSmall));
+        assert_eq!(encoded_size(-4), Err(Error::PsychoAcoustic));
+        assert_eq!(encoded_size(-5), Err(Error::Unknown(-5)));
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(Error::BadSampleFreq.to_string(), "unsupported sample rate");
+        assert_eq!(Error::Unknown(-7).to_string(), "LAME returned -7");
+        let err = io::Error::from(Error::AlreadyInitialized);
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "parameters are set after encoding began");
+    }
+
+    #[test]
+    fn test_set_after_init() {
+        let mut lame = initialized();
+        assert_eq!(lame.set_quality(2), Err(Error::AlreadyInitialized));
+        assert_eq!(lame.set_samplerate(44100), Err(Error::AlreadyInitialized));
+        assert_eq!(
+            lame.set_bitrate(Bitrate::Variable(2.0)),
+            Err(Error::AlreadyInitialized)
+        );
+        assert_eq!(
+            lame.set_tag(Tag::Title, "Song"),
+            Err(Error::AlreadyInitialized)
+        );
+        assert_eq!(lame.init_params(), Ok(()));
+        std::mem::forget(lame);
+    }
+
+    #[test]
+    fn test_channel_mismatch() {
+        let mut lame = initialized();
+        assert_eq!(lame.encode(&[0; 3], &[0; 2]), Err(Error::ChannelMismatch));
+        assert_eq!(lame.encode_float(&[0.0], &[]), Err(Error::ChannelMismatch));
+        std::mem::forget(lame);
+    }
+}
@@ -1,7 +1,13 @@
+use composer::generate::flac::FlacOptions;
+use composer::generate::master::{MasterOptions, Normalization};
+#[cfg(feature = "opus")]
+use composer::generate::opus::OpusOptions;
+#[cfg(feature = "vorbis")]
+use composer::generate::vorbis::VorbisOptions;
 use composer::generate::Generator;
-use composer::parse::parse;
+use composer::parse::{parse, Metadata};
 use composer::tokenize::tokenize;
-use lame::Lame;
+use lame::{Lame, Tag};
 use serenity::{
     async_trait,
     framework::standard::{
@@ -21,6 +27,7 @@ use songbird::{
 };
 use std::collections::VecDeque;
 use std::env;
+use std::io::Cursor;
 use std::sync::{Arc, Mutex};
 
 type MMLQueueItem = (Arc<Songbird>, (GuildId, ChannelId), I16Reader);
@@ -66,23 +73,33 @@ static TOKEN_NAME: &str = "DISCHORD_TOKEN";
 static MANUAL: &str = "```
 Dischord
 dc!help Dischordのヘルプを表示
-dc!play [MML] MMLを音声ファイルに書き出し
+dc!play [--format f] [MML] MMLを音声ファイルに書き出し (f: mp3, wav, flac, opus, ogg)
 dc!playraw [MML] MMLを圧縮されていない音声ファイルに書き出し
 
 Dischord MML 文法
 以下の文字列を連ねて記述します。小文字のアルファベット部分はパラメータとして整数を入れます。
 CDEFGABRn ドレミファソラシと休符に対応しています。数字を後ろにつけるとn分音符を表現します。
 \".\"は付点音符を表現します。\"&\"で長さを連結すると2つの長さをタイで接続します。
+n の代わりに%tと書くと全音符を192としたtカウント分の長さになります。
 <> オクターブを上げ(下げ)ます。
 () 括弧で囲んだ範囲の音を同時に発音します。
+@As,d,o 和音をs分音符のアルペジオで鳴らします。dは順番(0: 上昇, 1: 下降, 2: 往復)、oはオクターブ数です(s≦256, o≦8)。@A0で解除します。
 Tn テンポを後ろに表記された値に変更します。
+T~t,n n分の長さをかけてテンポを徐々にtへ変更します。
 Vn 音量を変更します。デフォルトは100です。
+V~v,n n分の長さをかけて音量を徐々にvへ変更します。
 Ln デフォルトの音符の長さを変更します。
-[]n 括弧で囲んだ範囲をn回繰り返します。
+@Sn,r n分音符2つの組の前半の長さをr‰にしてスウィングさせます。@S0で解除します。
+@Jt,v 発音時刻を最大tミリ秒、音量を最大v%ランダムにずらします。
+@K1 ドラムキットに切り替えます。C: キック, D: スネア, E: クローズドハイハット, F: オープンハイハット, G: タム, A: クラップ, B: 周期ノイズ。@K0で戻します。
+@Rn ノイズの乱数の種をnに設定します。
+[]n 括弧で囲んだ範囲をn回繰り返します(展開後のイベントは全体で1000000個まで)。
+{}n 括弧で囲んだ範囲をn分音符の長さに収めた連符にします。
 ; 複数の音を重ねるために、書き込み位置を先頭に戻します。
+#TITLE \"...\" / #COMPOSER \"...\" / #TRACK \"...\" 曲名 / 作曲者 / トラック名を設定します。
 @ 音を編集します。以下のコマンドが存在します。
 @n 音色を変更します。以下は指定できる波形の一覧です。
-0: 矩形波(デューティ比50%), 1: 矩形波(25%), 2: 矩形波(12.5%), 3: 三角波, 4: ノコギリ波, 5: サイン波, 6: ホワイトノイズ
+0: 矩形波(デューティ比50%), 1: 矩形波(25%), 2: 矩形波(12.5%), 3: 三角波, 4: ノコギリ波, 5: サイン波, 6: ホワイトノイズ, 7: 音程付きノイズ, 8: ファミコン風ノイズ(長周期), 9: ファミコン風ノイズ(短周期)
 @Ea,d,s,r ADSRエンベロープを設定します。
 @Dn,d n個の音をd/100%のデチューンで重ねて出力します。
 @H{...} 括弧内の16進文字列を4bit PCMとして登録します。
@@ -91,40 +108,148 @@ Ln デフォルトの音符の長さを変更します。
 @Tn 実際に鳴らされる周波数をn‰にします。
 @Fxn,... エフェクトを適用します。
 @FDd,f ディレイ(f‰フィードバック, dミリ秒)
+@FLf,q / @FHf,q / @FQt,f,q,g / @FEs,e,t,q ローパス / ハイパス / フィルター / フィルターエンベロープ
+@FRr,d,m リバーブ(部屋r‰, 減衰d‰, かかり具合m‰)
+@FCr,d,m / @FFr,d,f,m / @FPr,d,m コーラス / フランジャー / フェイザー
+@FOg,m / @FBn / @FSf ディストーション / ビットクラッシャー / サンプルレートリデューサー
+@FMt,r,a,l コンプレッサー(スレッショルドt‰, レシオr‰, アタックaミリ秒, リリースlミリ秒)
+@F0 エフェクトを解除します。
+@B{x}(@F...) / @X{x}n バスxにエフェクトを設定 / n%の音量で送ります。
 ```
 
 詳細なヘルプはこちら: https://github.com/Raclett3/dischord-rs/blob/master/MML.md";
 
-fn to_riff(mml: &str) -> Result<Vec<u8>, String> {
-    let tokens = tokenize(&mml)?;
-    let parsed = parse(&tokens).map_err(|x| x.to_string())?;
-    Ok(Generator::new(44100.0, &parsed).into_riff())
+/// Loudness of the files sent to the channel in LUFS
+const FILE_LOUDNESS: f32 = -14.0;
+
+fn file_master() -> MasterOptions {
+    MasterOptions {
+        normalization: Some(Normalization::Loudness(FILE_LOUDNESS)),
+        ..MasterOptions::default()
+    }
+}
+
+/// Title and composer of the song to send with the file
+fn describe(metadata: &Metadata) -> String {
+    match (&metadata.title, &metadata.composer) {
+        (Some(title), Some(composer)) => format!("「{}」 作曲: {}", title, composer),
+        (Some(title), None) => format!("「{}」", title),
+        (None, Some(composer)) => format!("作曲: {}", composer),
+        (None, None) => String::new(),
+    }
 }
 
-fn to_i16_samples(mml: &str) -> Result<Vec<i16>, String> {
+/// Formats of the files which `dc!play` sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Mp3,
+    Wav,
+    Flac,
+    #[cfg(feature = "opus")]
+    Opus,
+    #[cfg(feature = "vorbis")]
+    Vorbis,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mp3" => Some(Format::Mp3),
+            "wav" => Some(Format::Wav),
+            "flac" => Some(Format::Flac),
+            #[cfg(feature = "opus")]
+            "opus" => Some(Format::Opus),
+            #[cfg(feature = "vorbis")]
+            "ogg" | "vorbis" => Some(Format::Vorbis),
+            _ => None,
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Format::Mp3 => "result.mp3",
+            Format::Wav => "result.wav",
+            Format::Flac => "result.flac",
+            #[cfg(feature = "opus")]
+            Format::Opus => "result.opus",
+            #[cfg(feature = "vorbis")]
+            Format::Vorbis => "result.ogg",
+        }
+    }
+}
+
+/// Splits `--format f` off the beginning of the arguments
+fn parse_format(args: &str) -> Result<(Format, &str), String> {
+    let rest = match args.strip_prefix("--format") {
+        Some(rest) => rest.trim_start(),
+        None => return Ok((Format::Mp3, args)),
+    };
+    let (name, mml) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+    match Format::from_name(name) {
+        Some(format) => Ok((format, mml)),
+        None => Err(format!("形式 {} には対応していません。", name)),
+    }
+}
+
+fn to_file(mml: &str, format: Format) -> Result<(Vec<u8>, Metadata), String> {
     let tokens = tokenize(&mml)?;
     let parsed = parse(&tokens).map_err(|x| x.to_string())?;
-    Ok(Generator::new(44100.0, &parsed).into_i16_stream().collect())
+    let generator = |sample_rate| Generator::with_master(sample_rate, &parsed, file_master());
+    let unexpected = "予期せぬエラーが発生しました。";
+
+    let file = match format {
+        Format::Mp3 => {
+            let samples = generator(44100.0).render();
+            to_mp3(&samples, &parsed.metadata).map_err(|_| unexpected)?
+        }
+        Format::Wav => generator(44100.0).into_riff().map_err(|_| unexpected)?,
+        Format::Flac => {
+            let options = FlacOptions {
+                dither: true,
+                ..FlacOptions::default()
+            };
+            generator(44100.0)
+                .write_flac(Cursor::new(Vec::new()), options)
+                .map_err(|_| unexpected)?
+                .into_inner()
+        }
+        // Opus does not support 44.1 kHz
+        #[cfg(feature = "opus")]
+        Format::Opus => generator(48000.0)
+            .write_opus(Vec::new(), OpusOptions::default())
+            .map_err(|_| unexpected)?,
+        #[cfg(feature = "vorbis")]
+        Format::Vorbis => generator(44100.0)
+            .write_vorbis(Vec::new(), VorbisOptions::default())
+            .map_err(|_| unexpected)?,
+    };
+    Ok((file, parsed.metadata))
 }
 
-fn to_i16_stream(mml: &str) -> Result<I16Reader, String> {
+fn to_i16_stream(mml: &str) -> Result<(I16Reader, Metadata), String> {
     let tokens = tokenize(&mml)?;
     let parsed = parse(&tokens).map_err(|x| x.to_string())?;
-    Ok(I16Reader::new(Box::new(
-        Generator::new(48000.0, &parsed).into_i16_stream(),
-    )))
+    let reader = I16Reader::new(Box::new(Generator::new(48000.0, &parsed).into_i16_stream()));
+    Ok((reader, parsed.metadata))
 }
 
-fn to_mp3(samples: &[i16]) -> Option<Vec<u8>> {
-    let mut lame = Lame::init().ok()?;
+fn to_mp3(samples: &[f32], metadata: &Metadata) -> Result<Vec<u8>, lame::Error> {
+    let mut lame = Lame::init()?;
 
-    lame.set_quality(2).ok()?;
-    lame.set_kilobitrate(192).ok()?;
-    lame.set_channels(1).ok()?;
-    lame.set_samplerate(44100).ok()?;
+    lame.set_quality(2)?;
+    lame.set_kilobitrate(192)?;
+    lame.set_channels(1)?;
+    lame.set_samplerate(44100)?;
+    if let Some(title) = &metadata.title {
+        lame.set_tag(Tag::Title, title)?;
+    }
+    if let Some(composer) = &metadata.composer {
+        lame.set_tag(Tag::Artist, composer)?;
+    }
 
-    let mp3 = lame.encode_mono(&samples).ok()?;
-    Some(mp3)
+    let mut mp3 = lame.encode_float_mono(samples)?;
+    mp3.extend(lame.flush()?);
+    Ok(mp3)
 }
 
 #[command]
@@ -133,45 +258,42 @@ async fn help(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
-#[command]
-async fn playraw(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+/// Sends the MML as a file, or the error
+async fn send_file(ctx: &Context, msg: &Message, mml: &str, format: Format) -> CommandResult {
     msg.channel_id.say(&ctx.http, "生成しています...").await?;
 
-    let mml = args.rest();
-    let riff = match to_riff(mml) {
-        Ok(riff) => riff,
+    let (file, metadata) = match to_file(mml, format) {
+        Ok(file) => file,
         Err(err) => {
             msg.channel_id.say(&ctx.http, &err).await?;
             return Ok(());
         }
     };
 
-    let files = vec![(riff.as_slice(), "result.wav")];
-    msg.channel_id.send_files(&ctx.http, files, |x| x).await?;
+    let files = vec![(file.as_slice(), format.file_name())];
+    msg.channel_id
+        .send_files(&ctx.http, files, |x| match describe(&metadata) {
+            description if description.is_empty() => x,
+            description => x.content(description),
+        })
+        .await?;
     Ok(())
 }
 
 #[command]
-async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    msg.channel_id.say(&ctx.http, "生成しています...").await?;
+async fn playraw(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    send_file(ctx, msg, args.rest(), Format::Wav).await
+}
 
-    let mml = args.rest();
-    let samples = match to_i16_samples(mml) {
-        Ok(riff) => riff,
+#[command]
+async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match parse_format(args.rest()) {
+        Ok((format, mml)) => send_file(ctx, msg, mml, format).await,
         Err(err) => {
-            msg.channel_id.say(&ctx.http, &err).await?;
-            return Ok(());
+            msg.channel_id.say(&ctx.http, err).await?;
+            Ok(())
         }
-    };
-
-    if let Some(mp3) = to_mp3(&samples) {
-        let files = vec![(mp3.as_slice(), "result.mp3")];
-        msg.channel_id.send_files(&ctx.http, files, |x| x).await?;
-    } else {
-        msg.channel_id.say(&ctx.http, "予期せぬエラーが発生しました。").await?;
     }
-
-    Ok(())
 }
 
 #[command]
@@ -195,7 +317,7 @@ async fn vcplay(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     };
 
     let mml = args.rest();
-    let stream = match to_i16_stream(mml) {
+    let (stream, metadata) = match to_i16_stream(mml) {
         Ok(stream) => stream,
         Err(err) => {
             msg.channel_id.say(&ctx.http, err).await?;
@@ -214,9 +336,11 @@ async fn vcplay(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
         ));
     }
 
-    msg.channel_id
-        .say(&ctx.http, "キューに追加しました。")
-        .await?;
+    let message = match &metadata.title {
+        Some(title) => format!("「{}」をキューに追加しました。", title),
+        None => "キューに追加しました。".to_string(),
+    };
+    msg.channel_id.say(&ctx.http, message).await?;
 
     Ok(())
 }
@@ -1,95 +1,675 @@
 extern crate cpal;
 
-use composer::generate::Generator;
-use composer::parse::{parse, Instruction};
+use composer::format::format;
+use composer::generate::flac::FlacOptions;
+use composer::generate::master::MasterOptions;
+#[cfg(feature = "opus")]
+use composer::generate::opus::OpusOptions;
+#[cfg(feature = "vorbis")]
+use composer::generate::vorbis::VorbisOptions;
+use composer::generate::wav::WavOptions;
+use composer::generate::{count_notes, Generator};
+use composer::parse::{parse, ParsedMML};
+use composer::playback::{generator, lock, prepare_loop, Player, Region, Song, Watcher};
 use composer::tokenize::tokenize;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::io::{stdin, Read, Result};
-use std::sync::{
-    atomic::{AtomicBool, Ordering::SeqCst},
-    Arc,
-};
+use lame::{Lame, Mp3Writer, Tag};
+use std::fs::File;
+use std::io::{self, stdin, BufRead, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-fn main() -> Result<()> {
-    loop {
-        let mut buf = String::new();
-        stdin().lock().read_to_string(&mut buf)?;
-        let tokens = match tokenize(&buf) {
-            Ok(tokens) => tokens,
-            Err(err) => {
-                eprintln!("{}", err);
-                std::process::exit(1);
+const USAGE: &str = "usage: cli COMMAND [OPTIONS] [FILE]
+
+commands:
+    play      plays the song
+    watch     plays the song and reloads it whenever the file is saved
+    render    writes the song to a file
+    check     reports errors in the song
+    fmt       prints the song formatted
+    info      prints the length, the tracks and the notes of the song
+    devices   lists the output devices
+
+options:
+    -o, --output FILE         file to render to
+    -f, --format FORMAT       wav, mp3, flac, opus or ogg, which defaults to the extension of the output
+    -r, --sample-rate RATE    sample rate to render or play at
+    -d, --device NAME         output device to play through instead of the default one
+    --mute TRACKS             tracks not to play or render
+    --solo TRACKS             the only tracks to play or render
+    --stems DIRECTORY         renders each track into its own file in the directory
+    --from POSITION           position to play from
+    --to POSITION             position to stop playing at
+    --loop                    plays the part between --from and --to over and over
+    -w, --write               overwrites the file with the formatted song instead of printing it
+
+FILE is the standard input if it is - or omitted.
+TRACKS is a comma separated list of track names or numbers starting from 0.
+POSITION is the name of a marker written as @M{name}, or seconds.
+
+while watching, enter:
+    m    to mark the current position, which reloading restarts from instead
+    u    to remove the mark
+    r    to restart from the mark or the beginning
+    q    to quit";
+
+/// Samples given to LAME at once
+const MP3_CHUNK_SIZE: usize = 4096;
+/// How often the watched file and the player are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Play,
+    Watch,
+    Render,
+    Check,
+    Fmt,
+    Info,
+    Devices,
+}
+
+/// Formats of the rendered files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Wav,
+    Mp3,
+    Flac,
+    #[cfg(feature = "opus")]
+    Opus,
+    #[cfg(feature = "vorbis")]
+    Vorbis,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wav" => Some(Format::Wav),
+            "mp3" => Some(Format::Mp3),
+            "flac" => Some(Format::Flac),
+            #[cfg(feature = "opus")]
+            "opus" => Some(Format::Opus),
+            #[cfg(feature = "vorbis")]
+            "ogg" | "vorbis" => Some(Format::Vorbis),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Mp3 => "mp3",
+            Format::Flac => "flac",
+            #[cfg(feature = "opus")]
+            Format::Opus => "opus",
+            #[cfg(feature = "vorbis")]
+            Format::Vorbis => "ogg",
+        }
+    }
+
+    fn sample_rate(self) -> u32 {
+        match self {
+            // Opus does not support 44.1 kHz
+            #[cfg(feature = "opus")]
+            Format::Opus => 48000,
+            _ => 44100,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    file: Option<String>,
+    muted: Vec<String>,
+    soloed: Vec<String>,
+    stems: Option<PathBuf>,
+    output: Option<PathBuf>,
+    format: Option<Format>,
+    sample_rate: Option<u32>,
+    device: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    looping: bool,
+    write: bool,
+}
+
+impl Options {
+    /// Standard input is shown as `-` in the diagnostics
+    fn file_name(&self) -> &str {
+        self.file.as_deref().unwrap_or("-")
+    }
+}
+
+fn parse_tracks(tracks: &str) -> Vec<String> {
+    tracks.split(',').map(|track| track.to_string()).collect()
+}
+
+fn parse_args() -> Option<(Command, Options)> {
+    let mut args = std::env::args().skip(1);
+    let command = match args.next()?.as_str() {
+        "play" => Command::Play,
+        "watch" => Command::Watch,
+        "render" => Command::Render,
+        "check" => Command::Check,
+        "fmt" => Command::Fmt,
+        "info" => Command::Info,
+        "devices" => Command::Devices,
+        _ => return None,
+    };
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(PathBuf::from(args.next()?)),
+            "-f" | "--format" => options.format = Some(Format::from_name(&args.next()?)?),
+            "-r" | "--sample-rate" => {
+                options.sample_rate = Some(args.next()?.parse().ok().filter(|&x| x > 0)?)
             }
-        };
-        let parsed = match parse(&tokens) {
-            Ok(parsed) => parsed,
-            Err(err) => {
-                eprintln!("{}", err);
-                std::process::exit(1);
+            "-d" | "--device" => options.device = Some(args.next()?),
+            "--mute" => options.muted.extend(parse_tracks(&args.next()?)),
+            "--solo" => options.soloed.extend(parse_tracks(&args.next()?)),
+            "--stems" => options.stems = Some(PathBuf::from(args.next()?)),
+            "--from" => options.from = Some(args.next()?),
+            "--to" => options.to = Some(args.next()?),
+            "--loop" => options.looping = true,
+            "-w" | "--write" => options.write = true,
+            "-" if options.file.is_none() => (),
+            file if options.file.is_none() && !file.starts_with('-') => {
+                options.file = Some(file.to_string())
             }
+            _ => return None,
+        }
+    }
+    Some((command, options))
+}
+
+fn read_source(options: &Options) -> Result<String, String> {
+    let mut source = String::new();
+    match &options.file {
+        Some(file) => File::open(file).and_then(|mut file| file.read_to_string(&mut source)),
+        None => stdin().lock().read_to_string(&mut source),
+    }
+    .map_err(|err| format!("{}: {}", options.file_name(), err))?;
+    Ok(source)
+}
+
+/// Line and column of the character at the position, both counted from 1
+fn line_column(source: &str, position: usize) -> (usize, usize) {
+    let before: Vec<_> = source.chars().take(position.saturating_sub(1)).collect();
+    let line = before.iter().filter(|&&x| x == '\n').count() + 1;
+    let column = before.iter().rev().take_while(|&&x| x != '\n').count() + 1;
+    (line, column)
+}
+
+/// Parses the source, or returns the error with where it is
+fn parse_source(source: &str, file_name: &str) -> Result<ParsedMML, String> {
+    let tokens = tokenize(source).map_err(|err| format!("{}: {}", file_name, err))?;
+    parse(&tokens).map_err(|err| match err.position() {
+        Some(position) => {
+            let (line, column) = line_column(source, position);
+            format!("{}:{}:{}: {}", file_name, line, column, err)
+        }
+        None => format!("{}: {}", file_name, err),
+    })
+}
+
+/// Indices of the muted tracks and the soloed tracks
+type Tracks = (Vec<usize>, Vec<usize>);
+
+/// Index of the track with the name, or of the number
+fn find_track(mml: &ParsedMML, track: &str) -> Result<usize, String> {
+    mml.track_index(track)
+        .or_else(|| track.parse().ok().filter(|&x| x < mml.tracks.len()))
+        .ok_or_else(|| format!("track {} does not exist", track))
+}
+
+fn find_tracks(mml: &ParsedMML, options: &Options) -> Result<Tracks, String> {
+    let find = |tracks: &[String]| -> Result<Vec<_>, _> {
+        tracks.iter().map(|x| find_track(mml, x)).collect()
+    };
+    Ok((find(&options.muted)?, find(&options.soloed)?))
+}
+
+fn write_mp3<W: Write>(generator: Generator, writer: W, sample_rate: u32) -> io::Result<W> {
+    let mut lame = Lame::init()?;
+    lame.set_channels(1)?;
+    lame.set_samplerate(sample_rate as i32)?;
+    lame.set_quality(2)?;
+    lame.set_kilobitrate(192)?;
+    let metadata = generator.metadata();
+    if let Some(title) = &metadata.title {
+        lame.set_tag(Tag::Title, title)?;
+    }
+    if let Some(composer) = &metadata.composer {
+        lame.set_tag(Tag::Artist, composer)?;
+    }
+
+    let mut mp3 = Mp3Writer::new(lame, writer);
+    let mut samples = generator.peekable();
+    while samples.peek().is_some() {
+        let chunk: Vec<_> = samples.by_ref().take(MP3_CHUNK_SIZE).collect();
+        mp3.write_float_mono(&chunk)?;
+    }
+    mp3.finish()
+}
+
+fn write_file(
+    generator: Generator,
+    path: &Path,
+    format: Format,
+    sample_rate: u32,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Wav => generator.write_wav(file, WavOptions::default())?,
+        Format::Mp3 => write_mp3(generator, file, sample_rate)?,
+        Format::Flac => generator.write_flac(file, FlacOptions::default())?,
+        #[cfg(feature = "opus")]
+        Format::Opus => generator.write_opus(file, OpusOptions::default())?,
+        #[cfg(feature = "vorbis")]
+        Format::Vorbis => generator.write_vorbis(file, VorbisOptions::default())?,
+    };
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+/// The number of the track followed by its name, which cannot leave the directory or clash with
+/// another track as the characters other than letters, digits, `-` and `_` are replaced
+fn stem_name(mml: &ParsedMML, track: usize) -> String {
+    let name = match &mml.track_names[track] {
+        Some(name) => name,
+        None => return format!("{:02}", track),
+    };
+    let name: String = name
+        .chars()
+        .map(|x| match x {
+            x if x.is_alphanumeric() || x == '-' => x,
+            _ => '_',
+        })
+        .collect();
+    format!("{:02}-{}", track, name)
+}
+
+/// Writes each track into its own file in the directory, named after the track
+fn write_stems(
+    directory: &Path,
+    mml: &ParsedMML,
+    format: Format,
+    sample_rate: u32,
+) -> io::Result<()> {
+    std::fs::create_dir_all(directory)?;
+    let stems = Generator::stems(sample_rate as f32, mml, MasterOptions::default());
+    for (i, stem) in stems.into_iter().enumerate() {
+        let path = directory.join(format!("{}.{}", stem_name(mml, i), format.extension()));
+        write_file(stem, &path, format, sample_rate)?;
+    }
+    Ok(())
+}
+
+fn render(mml: &ParsedMML, options: &Options) -> Result<(), String> {
+    if let Some(directory) = &options.stems {
+        let format = options.format.unwrap_or(Format::Wav);
+        let sample_rate = options.sample_rate.unwrap_or_else(|| format.sample_rate());
+        return write_stems(directory, mml, format, sample_rate)
+            .map_err(|err| format!("{}: {}", directory.display(), err));
+    }
+
+    let path = match &options.output {
+        Some(path) => path,
+        None => return Err("render needs --output or --stems".to_string()),
+    };
+    let extension = path.extension().and_then(|x| x.to_str());
+    let format = match options.format.or_else(|| Format::from_name(extension?)) {
+        Some(format) => format,
+        None => return Err(format!("{}: give the format with --format", path.display())),
+    };
+    let sample_rate = options.sample_rate.unwrap_or_else(|| format.sample_rate());
+    let (muted, soloed) = find_tracks(mml, options)?;
+    let generator = generator(sample_rate, mml, &muted, &soloed);
+    write_file(generator, path, format, sample_rate)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn minutes(seconds: f32) -> String {
+    format!("{}:{:05.2}", (seconds / 60.0) as usize, seconds % 60.0)
+}
+
+fn info(mml: &ParsedMML) {
+    if let Some(title) = &mml.metadata.title {
+        println!("title: {}", title);
+    }
+    if let Some(composer) = &mml.metadata.composer {
+        println!("composer: {}", composer);
+    }
+    let generator = Generator::new(44100.0, mml);
+    println!("length: {}", minutes(generator.track_length()));
+    println!("tracks: {}", mml.tracks.len());
+    let mut total = 0;
+    for (i, track) in mml.tracks.iter().enumerate() {
+        let notes = count_notes(track);
+        total += notes;
+        match &mml.track_names[i] {
+            Some(name) => println!("    {} {}: {} notes", i, name, notes),
+            None => println!("    {}: {} notes", i, notes),
+        }
+    }
+    println!("notes: {}", total);
+    if !generator.markers().is_empty() {
+        println!("markers:");
+        for (name, position) in generator.markers() {
+            println!("    {}: {}", name, minutes(*position));
+        }
+    }
+}
+
+fn fmt(options: &Options) -> Result<(), String> {
+    if options.write && options.file.is_none() {
+        return Err("--write needs a file".to_string());
+    }
+    let source = read_source(options)?;
+    let tokens = tokenize(&source).map_err(|err| format!("{}: {}", options.file_name(), err))?;
+    let formatted = format(&tokens);
+    match &options.file {
+        Some(file) if options.write => {
+            std::fs::write(file, formatted).map_err(|err| format!("{}: {}", file, err))
+        }
+        _ => {
+            print!("{}", formatted);
+            Ok(())
+        }
+    }
+}
+
+fn devices() -> Result<(), String> {
+    let host = cpal::default_host();
+    let default = host.default_output_device().and_then(|x| x.name().ok());
+    let devices = host
+        .output_devices()
+        .map_err(|err| format!("failed to list the output devices: {}", err))?;
+    for device in devices {
+        let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
+        let mark = if Some(&name) == default.as_ref() {
+            "*"
+        } else {
+            " "
         };
-    
-        let host = cpal::default_host();
-    
-        let device = host
-            .default_output_device()
-            .expect("failed to find a default output device");
-        let config = device.default_output_config().unwrap();
-    
-        match config.sample_format() {
-            cpal::SampleFormat::F32 => play::<f32>(&device, &config.into(), &parsed).unwrap(),
-            cpal::SampleFormat::I16 => play::<i16>(&device, &config.into(), &parsed).unwrap(),
-            cpal::SampleFormat::U16 => play::<u16>(&device, &config.into(), &parsed).unwrap(),
+        println!("{} {}", mark, name);
+    }
+    Ok(())
+}
+
+fn find_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device, String> {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return host
+                .default_output_device()
+                .ok_or_else(|| "no output device is available".to_string())
         }
+    };
+    host.output_devices()
+        .map_err(|err| format!("failed to list the output devices: {}", err))?
+        .find(|device| device.name().map(|x| x == name).unwrap_or(false))
+        .ok_or_else(|| format!("output device {} does not exist", name))
+}
+
+/// Position in samples of the marker, or of the seconds
+fn find_position(generator: &Generator, sample_rate: u32, position: &str) -> Result<usize, String> {
+    let seconds = generator
+        .marker(position)
+        .or_else(|| position.parse().ok().filter(|&x: &f32| x >= 0.0))
+        .ok_or_else(|| format!("marker {} does not exist", position))?;
+    Ok((seconds * sample_rate as f32).round() as usize)
+}
+
+fn find_region(mml: &ParsedMML, options: &Options, sample_rate: u32) -> Result<Region, String> {
+    let generator = Generator::new(sample_rate as f32, mml);
+    let find = |position: &Option<String>| {
+        position
+            .as_deref()
+            .map(|x| find_position(&generator, sample_rate, x))
+            .transpose()
+    };
+    let start = find(&options.from)?;
+    let end = find(&options.to)?;
+
+    let length = (generator.track_length() * sample_rate as f32) as usize;
+    if start.is_some_and(|start| length <= start) {
+        return Err("--from is after the end of the song".to_string());
     }
+    let start = start.unwrap_or(0);
+    if end.is_some_and(|end| end <= start) {
+        return Err("--to is not after --from".to_string());
+    }
+    Ok(Region {
+        start,
+        end,
+        looping: options.looping,
+    })
 }
 
-fn play<T: cpal::Sample>(
-    device: &cpal::Device,
-    config: &cpal::StreamConfig,
-    tracks: &[Vec<Instruction>],
-) -> Option<()> {
-    let sample_rate = config.sample_rate.0 as f32;
-    let channels = config.channels as usize;
+fn load_song(mml: ParsedMML, options: &Options, sample_rate: u32) -> Result<Song, String> {
+    let (muted, soloed) = find_tracks(&mml, options)?;
+    Ok(Song {
+        region: find_region(&mml, options, sample_rate)?,
+        mml,
+        muted,
+        soloed,
+    })
+}
 
-    println!("generating...");
-    let mut generator = Generator::new(sample_rate, tracks);
-    println!("generated! length: {:.2}s", generator.track_length());
-    let is_over = Arc::new(AtomicBool::new(false));
-    let is_over_cloned = is_over.clone();
+/// The device to play through, opened once and kept open while the songs change
+struct Output {
+    device: cpal::Device,
+    config: cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+}
 
-    let stream = device
-        .build_output_stream(
+impl Output {
+    fn open(options: &Options) -> Result<Self, String> {
+        let host = cpal::default_host();
+        let device = find_device(&host, options.device.as_deref())?;
+        let supported = device
+            .default_output_config()
+            .map_err(|err| format!("failed to get the output config: {}", err))?;
+        let mut config = supported.config();
+        if let Some(sample_rate) = options.sample_rate {
+            config.sample_rate = cpal::SampleRate(sample_rate);
+        }
+        Ok(Output {
+            device,
             config,
-            move |data: &mut [T], _| write_samples(data, channels, &mut generator, &is_over_cloned),
-            |err| eprintln!("an error occurred on stream: {}", err),
-        )
-        .ok()?;
-    stream.play().ok()?;
+            sample_format: supported.sample_format(),
+        })
+    }
 
-    println!("playing...");
-    while !is_over.load(SeqCst) {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
     }
-    println!("I finished playing the music. What's next?");
 
-    Some(())
+    /// Starts the stream, which plays silence while the player has nothing to play
+    fn play(&self, player: &Arc<Mutex<Player>>) -> Result<cpal::Stream, String> {
+        match self.sample_format {
+            cpal::SampleFormat::F32 => self.build_stream::<f32>(player.clone()),
+            cpal::SampleFormat::I16 => self.build_stream::<i16>(player.clone()),
+            cpal::SampleFormat::U16 => self.build_stream::<u16>(player.clone()),
+        }
+    }
+
+    fn build_stream<T: cpal::Sample>(
+        &self,
+        player: Arc<Mutex<Player>>,
+    ) -> Result<cpal::Stream, String> {
+        let channels = self.config.channels as usize;
+        let stream = self
+            .device
+            .build_output_stream(
+                &self.config,
+                move |data: &mut [T], _| write_samples(data, channels, &player),
+                |err| eprintln!("an error occurred on stream: {}", err),
+            )
+            .map_err(|err| format!("failed to open the output stream: {}", err))?;
+        stream
+            .play()
+            .map_err(|err| format!("failed to play: {}", err))?;
+        Ok(stream)
+    }
 }
 
-fn write_samples<T: cpal::Sample>(
-    data: &mut [T],
-    channels: usize,
-    generator: &mut Generator,
-    is_over: &Arc<AtomicBool>,
-) {
+fn write_samples<T: cpal::Sample>(data: &mut [T], channels: usize, player: &Mutex<Player>) {
+    let mut player = lock(player);
     for frame in data.chunks_mut(channels) {
-        let sample = generator.next().unwrap_or_else(|| {
-            is_over.store(true, SeqCst);
-            0.0
-        });
+        let sample = player.next_sample();
         let value: T = cpal::Sample::from(&sample);
         for sample in frame.iter_mut() {
             *sample = value;
         }
     }
 }
+
+fn play_song(mml: ParsedMML, options: &Options) -> Result<(), String> {
+    let output = Output::open(options)?;
+    let sample_rate = output.sample_rate();
+    let song = load_song(mml, options, sample_rate)?;
+
+    println!("generating...");
+    let region = song.region;
+    let generator = song.generator_at(sample_rate, region.start);
+    println!("generated! length: {:.2}s", generator.track_length());
+    if let Some(title) = &song.mml.metadata.title {
+        println!("title: {}", title);
+    }
+    let player = Arc::new(Mutex::new(Player::default()));
+    lock(&player).start(generator, region.start, region);
+    let _stream = output.play(&player)?;
+
+    println!("playing...");
+    while !lock(&player).is_over() {
+        prepare_loop(&player, &song, sample_rate);
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    println!("I finished playing the music. What's next?");
+
+    Ok(())
+}
+
+/// Lines entered while watching, read on their own thread so that checking the file does not wait
+fn read_commands() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line.trim().to_string()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Parses the song, or returns what is wrong with it
+fn watched_song(source: &str, options: &Options, sample_rate: u32) -> Result<Song, String> {
+    parse_source(source, options.file_name()).and_then(|mml| load_song(mml, options, sample_rate))
+}
+
+/// Plays the song, and plays it again from the mark or where it was whenever the file is saved
+fn watch(options: &Options) -> Result<(), String> {
+    let path = match &options.file {
+        Some(file) => file,
+        None => return Err("watch needs a file".to_string()),
+    };
+    let mut modified = modified_time(path);
+    let mut source = read_source(options)?;
+    let output = Output::open(options)?;
+    let sample_rate = output.sample_rate();
+    let song = watched_song(&source, options, sample_rate).map_err(|err| eprintln!("{}", err));
+    let mut watcher = Watcher::new(sample_rate, song.ok());
+    let seconds = |position: usize| position as f32 / sample_rate as f32;
+    let player = Arc::new(Mutex::new(Player::default()));
+    let _stream = output.play(&player)?;
+    let commands = read_commands();
+
+    println!("watching {}...", path);
+    loop {
+        if let Some(position) = watcher.update(&player) {
+            println!("{}: playing from {:.2}s", path, seconds(position));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+
+        match commands.try_recv().as_ref().map(|x| x.as_str()) {
+            Ok("m") => {
+                let position = watcher.set_mark(&lock(&player));
+                println!("marked {:.2}s", seconds(position));
+            }
+            Ok("u") => {
+                watcher.remove_mark();
+                println!("removed the mark");
+            }
+            Ok("r") => watcher.restart(),
+            Ok("q") => return Ok(()),
+            Ok(_) => println!("enter m, u, r or q"),
+            Err(_) => {}
+        }
+
+        // Editors may write the file more than once or without changing it
+        if modified_time(path) == modified {
+            continue;
+        }
+        modified = modified_time(path);
+        let changed = match read_source(options) {
+            Ok(changed) if changed != source => changed,
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        source = changed;
+        let changed = watched_song(&source, options, sample_rate);
+        if let Err(err) = watcher.reload(&lock(&player), changed) {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+fn load(options: &Options) -> Result<ParsedMML, String> {
+    parse_source(&read_source(options)?, options.file_name())
+}
+
+fn run(command: Command, options: &Options) -> Result<(), String> {
+    match command {
+        Command::Play => play_song(load(options)?, options),
+        Command::Watch => watch(options),
+        Command::Render => render(&load(options)?, options),
+        Command::Check => {
+            load(options)?;
+            println!("{}: ok", options.file_name());
+            Ok(())
+        }
+        Command::Fmt => fmt(options),
+        Command::Info => {
+            info(&load(options)?);
+            Ok(())
+        }
+        Command::Devices => devices(),
+    }
+}
+
+fn main() {
+    let (command, options) = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(command, &options) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}